@@ -1,5 +1,61 @@
-// fn alias_method(p: Vec<f32>) -> vec<(f32, u32)> {
-//     let n = p.len();
-//
-//     return vec![0.0,0]
-// }
\ No newline at end of file
+use crate::model::ifs::IFS;
+
+/// One column of an alias table. The kernel picks a column uniformly, keeps it with probability `prob`,
+/// and otherwise takes `alias`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AliasEntry {
+    pub prob: f32,
+    pub alias: i32,
+}
+
+/// Builds a table that samples index i with probability weights[i] / sum(weights), with Vose's method.
+/// Negative and NaN weights count as zero. If nothing has any weight, every column aliases to -1,
+/// which the kernel reads as "no outgoing weight".
+pub fn alias_table(weights: &[f64]) -> Vec<AliasEntry> {
+    let n = weights.len();
+    let weights: Vec<f64> = weights.iter().map(|w| if *w > 0.0 { *w } else { 0.0 }).collect();
+    let sum: f64 = weights.iter().sum();
+    if sum <= 0.0 || !sum.is_finite() {
+        return vec![AliasEntry { prob: 0.0, alias: -1 }; n];
+    }
+
+    let mut scaled: Vec<f64> = weights.iter().map(|w| w * n as f64 / sum).collect();
+    let mut table = vec![AliasEntry { prob: 1.0, alias: 0 }; n];
+    let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+
+    while let (Some(&l), Some(&g)) = (small.last(), large.last()) {
+        small.pop();
+        large.pop();
+        table[l] = AliasEntry { prob: scaled[l] as f32, alias: g as i32 };
+        scaled[g] = scaled[g] + scaled[l] - 1.0;
+        if scaled[g] < 1.0 {
+            small.push(g);
+        } else {
+            large.push(g);
+        }
+    }
+    // whatever is left over is 1 give or take rounding, and always keeps itself
+    for i in large.into_iter().chain(small) {
+        table[i] = AliasEntry { prob: 1.0, alias: i as i32 };
+    }
+    table
+}
+
+/// Where points start after a reset, by iterator index. Falls back to uniform so points always start somewhere.
+pub fn start_alias_table(model: &IFS) -> Vec<AliasEntry> {
+    let mut start_weights: Vec<f64> = model.iterators.iter().map(|it| (it.start_weight * it.base_weight) as f64).collect();
+    if start_weights.iter().all(|w| *w <= 0.0) {
+        start_weights.fill(1.0);
+    }
+    alias_table(&start_weights)
+}
+
+/// One table per iterator for where points go next, itnum x itnum, row major by the iterator they leave
+pub fn xaos_alias_tables(model: &IFS) -> Vec<AliasEntry> {
+    model.iterators.iter()
+        .flat_map(|from| {
+            let weights: Vec<f64> = model.iterators.iter().map(|to| model[(from, to)] * to.base_weight as f64).collect();
+            alias_table(&weights)
+        })
+        .collect()
+}
@@ -1,11 +1,12 @@
 use std::hash::Hash;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::mpsc::{SyncSender, TryRecvError};
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::Duration;
-use egui::{Frame, TextureId, widgets};
+use strum::IntoEnumIterator;
+use egui::{Frame, Key, KeyboardShortcut, Modifiers, TextureId, widgets};
 use rand::random;
 use crate::editors::affine_editor::AffineEditor;
 use crate::editors::animation_editor::AnimationEditor;
@@ -14,26 +15,65 @@ use crate::editors::palette_editor::PaletteEditor;
 use crate::editors::response_curve_editor::ResponseCurveEditor;
 use crate::editors::weight_graph_editor::WeightGraphEditor;
 use crate::model::ifs::IFS;
-use crate::rendering::graphics_engine::GraphicsEngine;
+use crate::model::iterator::Iterator;
+use crate::model::transform::Transform;
+use crate::model::transform_library::TransformLibrary;
+use crate::rendering::accumulation::Accumulation;
+use crate::rendering::graphics_engine::{EngineChannels, EngineRequest, GraphicsEngine, RenderStats};
+use crate::rendering::image_export::ImageFormat;
+use crate::rendering::tonal_histogram::TonalHistogram;
+use crate::rendering::transform_validation::TransformError;
 use crate::viewport::Viewport;
 
 const UPPER_BOUND: u16 = u16::MAX; //for when we need an inclusive range on something that should have no upper bound
+const TRANSFORM_DIRS: [&str; 1] = ["transforms"];
+const LOAD_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::L);
+const SAVE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::S);
+const SAVE_IMAGE_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::S);
+/// Buttons on an iterator's row, applied once the list is done drawing
+enum IteratorAction {
+  Move(i32, usize),
+  Duplicate(i32),
+  Delete(i32),
+}
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 //#[derive(serde::Deserialize, serde::Serialize)]
 //#[serde(skip)] // if we add new fields, give them default values when deserializing old state
 pub struct Display<'a> {
   engine_pipe: Option<SyncSender<IFS>>,
   app_rx: Option<Receiver<TextureId>>,
+  errors_rx: Option<Receiver<Vec<TransformError>>>,
+  tonal_rx: Option<Receiver<TonalHistogram>>,
+  tonal_histogram: Option<TonalHistogram>, //the latest from the engine, for the response curve editor
+  requests_tx: Option<SyncSender<EngineRequest>>,
+  status_tx: Option<SyncSender<anyhow::Result<String>>>, //handed to the engine with each request
+  status_rx: Option<Receiver<anyhow::Result<String>>>,
+  stats_rx: Option<Receiver<RenderStats>>,
+  stats: RenderStats, //the latest from the engine
+  transform_errors: Vec<TransformError>, //from the last time the engine built the kernel
   ifs: IFS,
   ifs_hash: u64,
+  display_hash: u64, //brightness, gamma and such, these don't restart the render
+  transforms: TransformLibrary,
+  // world file
+  world_path: Option<PathBuf>,
+  world_hashes: (u64, u64), //get_hash and settings_hash as of this frame
+  saved_hashes: (u64, u64), //the same when last loaded/saved, to tell if it's dirty
+  window_title: String,
+  error_message: Option<String>,
   // image settings
   lock_aspect_ratio: bool,
+  aspect_ratio: Option<f64>, //width over height, while it's locked
+  show_save_image: bool,
+  image_format: ImageFormat,
+  image_alpha: bool, //transparent background
+  status: Option<String>, //how the last save or resume went
 
   //anim settings
   anim_frame: usize,
   batch_dir: &'a Path, //where to export animation frames
   use_batch_mode: bool, //are we exporting an animation?
-  use_stopping_sl: bool, //remove this, but SL is the sampling depth at which we incr. anim_frame
   //windows
   show_rcurves: bool,
   show_palette: bool,
@@ -58,14 +98,34 @@ impl Default for Display<'_> {
     Self {
       engine_pipe: None,
       app_rx: None,
+      errors_rx: None,
+      tonal_rx: None,
+      tonal_histogram: None,
+      requests_tx: None,
+      status_tx: None,
+      status_rx: None,
+      stats_rx: None,
+      stats: RenderStats::default(),
+      transform_errors: vec![],
+      world_hashes: (ifs.get_hash(), ifs.settings_hash()),
+      saved_hashes: (ifs.get_hash(), ifs.settings_hash()),
       ifs: ifs,
       ifs_hash: 0,
+      display_hash: 0,
+      transforms: load_transforms(),
+      world_path: None,
+      window_title: String::new(),
+      error_message: None,
       lock_aspect_ratio: true,
+      aspect_ratio: None,
+      show_save_image: false,
+      image_format: ImageFormat::Png8,
+      image_alpha: false,
+      status: None,
 
       anim_frame: 0,
       batch_dir: Path::new("."),
       use_batch_mode: false,
-      use_stopping_sl: false,
       show_rcurves: false,
       show_affines: false,
       show_weights: false,
@@ -98,13 +158,19 @@ impl Display<'_> {
     let (work_status_tx, work_status_rx) = mpsc::sync_channel(1);
     let (ifs_tx, ifs_rx) = mpsc::sync_channel(1);
     let (app_tx, app_rx) = mpsc::sync_channel(60);
+    let (errors_tx, errors_rx) = mpsc::sync_channel(1);
+    let (tonal_tx, tonal_rx) = mpsc::sync_channel(1);
+    let (requests_tx, requests_rx) = mpsc::sync_channel(4);
+    let (status_tx, status_rx) = mpsc::sync_channel(4);
+    let (stats_tx, stats_rx) = mpsc::sync_channel(1);
 
     let binding = &cc.wgpu_render_state;
     let wgpu = binding.as_ref().expect("wgpu??").clone();
 
     let _ = work_status_tx.send(());
 
-    let mut engine = GraphicsEngine::new_engine(&wgpu, work_status_tx, ifs_rx, app_tx);
+    let channels = EngineChannels { ifs_rx, app_tx, errors_tx, tonal_tx, requests_rx, stats_tx };
+    let mut engine = GraphicsEngine::new_engine(&wgpu, work_status_tx, channels);
     thread::spawn(move || {
       loop {
         if work_status_rx.recv_timeout(Duration::from_millis(100)).is_ok() {
@@ -118,13 +184,213 @@ impl Display<'_> {
     //return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
     //}
 
-    Self {
+    let mut display = Self {
       engine_pipe: Some(ifs_tx),
       app_rx: Some(app_rx),
+      errors_rx: Some(errors_rx),
+      tonal_rx: Some(tonal_rx),
+      requests_tx: Some(requests_tx),
+      status_tx: Some(status_tx),
+      status_rx: Some(status_rx),
+      stats_rx: Some(stats_rx),
       ..Self::default()
+    };
+    if !display.transforms.errors.is_empty() {
+      let errors: Vec<String> = display.transforms.errors.iter()
+        .map(|e| format!("{}: {}", e.path.display(), e.message))
+        .collect();
+      display.error_message = Some(format!("Some transforms failed to load:\n{}", errors.join("\n")));
     }
+    display
   }
   // pub fn new()
+
+  fn is_dirty(&self) -> bool {
+    self.world_hashes != self.saved_hashes
+  }
+
+  fn mark_saved(&mut self) {
+    self.world_hashes = (self.ifs.get_hash(), self.ifs.settings_hash());
+    self.saved_hashes = self.world_hashes;
+  }
+
+  /// Native worlds (.toml) and IFSRenderer worlds (.ifsjson) are both accepted.
+  /// IFSRenderer worlds don't get a path, so the next save asks where to put the native file.
+  fn load_world(&mut self, path: PathBuf) {
+    let is_ifsjson = path.extension().is_some_and(|e| e == "ifsjson" || e == "json");
+    let loaded = if is_ifsjson {
+      IFS::load_ifsjson(&path, |name, version| self.transforms.get(name, version).cloned())
+    } else {
+      IFS::load_world(&path)
+    };
+    match loaded {
+      Ok(ifs) => {
+        self.ifs = ifs;
+        self.aspect_ratio = None;
+        self.world_path = if is_ifsjson { None } else { Some(path) };
+        self.mark_saved();
+      }
+      Err(e) => self.error_message = Some(format!("{e:#}")),
+    }
+  }
+
+  fn save_world(&mut self, path: PathBuf) {
+    match self.ifs.save_world(&path) {
+      Ok(_) => {
+        self.world_path = Some(path);
+        self.mark_saved();
+      }
+      Err(e) => self.error_message = Some(format!("{e:#}")),
+    }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn load_dialog(&mut self) {
+    let picked = rfd::FileDialog::new()
+      .add_filter("Worlds", &["toml", "ifsjson", "json"])
+      .add_filter("IFSRS world", &["toml"])
+      .add_filter("IFSRenderer world", &["ifsjson", "json"])
+      .pick_file();
+    if let Some(path) = picked {
+      self.load_world(path);
+    }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn save_dialog(&mut self) {
+    if let Some(path) = self.world_path.clone() {
+      self.save_world(path);
+      return;
+    }
+    let picked = rfd::FileDialog::new()
+      .add_filter("IFSRS world", &["toml"])
+      .set_file_name(format!("{}.toml", self.ifs.title))
+      .save_file();
+    if let Some(path) = picked {
+      self.save_world(path);
+    }
+  }
+
+  /// Hands the engine a request, which lets us know how it went through `status_rx`
+  fn request(&mut self, request: impl FnOnce(SyncSender<anyhow::Result<String>>) -> EngineRequest, status: String) {
+    let (Some(requests_tx), Some(done)) = (&self.requests_tx, &self.status_tx) else {
+      return;
+    };
+    self.status = Some(match requests_tx.try_send(request(done.clone())) {
+      Ok(_) => status,
+      Err(_) => "Still busy with the last saves, try again in a moment".to_owned(),
+    });
+  }
+
+  /// The image is written in the background
+  fn save_image(&mut self, path: PathBuf) {
+    let (format, alpha) = (self.image_format, self.image_alpha);
+    let status = format!("Saving {}...", path.display());
+    self.request(|done| EngineRequest::SaveImage { path, format, alpha, done }, status);
+  }
+
+  fn save_accumulation(&mut self, path: PathBuf) {
+    let status = format!("Saving {}...", path.display());
+    self.request(|done| EngineRequest::SaveAccumulation { path, done }, status);
+  }
+
+  /// Several files are merged into one first. They're read on a thread of their own, then handed to the engine.
+  fn resume_accumulations(&mut self, paths: Vec<PathBuf>) {
+    let (Some(requests_tx), Some(done)) = (self.requests_tx.clone(), self.status_tx.clone()) else {
+      return;
+    };
+    self.status = Some(format!("Loading {} accumulation(s)...", paths.len()));
+    thread::spawn(move || {
+      match Accumulation::load_merged(&paths) {
+        Ok(acc) => { let _ = requests_tx.send(EngineRequest::Resume { accumulation: Box::new(acc), done }); }
+        Err(e) => { let _ = done.send(Err(e)); }
+      }
+    });
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn save_accumulation_dialog(&mut self) {
+    let picked = rfd::FileDialog::new()
+      .add_filter("Accumulation", &["ifsacc"])
+      .set_file_name(format!("{}.ifsacc", self.ifs.title))
+      .save_file();
+    if let Some(path) = picked {
+      self.save_accumulation(path);
+    }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn resume_dialog(&mut self) {
+    let picked = rfd::FileDialog::new()
+      .add_filter("Accumulation", &["ifsacc"])
+      .pick_files();
+    if let Some(paths) = picked {
+      self.resume_accumulations(paths);
+    }
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  fn save_image_dialog(&mut self) {
+    let ext = self.image_format.extension();
+    let picked = rfd::FileDialog::new()
+      .add_filter(self.image_format.name(), &[ext])
+      .set_file_name(format!("{}.{ext}", self.ifs.title))
+      .save_file();
+    if let Some(path) = picked {
+      self.save_image(path);
+    }
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  fn load_dialog(&mut self) {}
+  #[cfg(target_arch = "wasm32")]
+  fn save_dialog(&mut self) {}
+  #[cfg(target_arch = "wasm32")]
+  fn save_image_dialog(&mut self) {}
+  #[cfg(target_arch = "wasm32")]
+  fn save_accumulation_dialog(&mut self) {}
+  #[cfg(target_arch = "wasm32")]
+  fn resume_dialog(&mut self) {}
+
+  /// Format and background for the image, saved at the world's size rather than the viewport's
+  fn save_image_window(&mut self, ctx: &egui::Context) {
+    let mut open = self.show_save_image;
+    egui::Window::new("Save image")
+      .open(&mut open)
+      .collapsible(false)
+      .resizable(false)
+      .show(ctx, |ui| {
+        ui.label(format!("{} x {}", self.ifs.width, self.ifs.height));
+        egui::ComboBox::from_label("Format")
+          .selected_text(self.image_format.name())
+          .show_ui(ui, |ui| {
+            for format in ImageFormat::iter() {
+              ui.selectable_value(&mut self.image_format, format, format.name());
+            }
+          });
+        ui.checkbox(&mut self.image_alpha, "Transparent background");
+        if ui.button("Save...").clicked() {
+          self.save_image_dialog();
+        }
+        if let Some(status) = &self.status {
+          ui.label(status);
+        }
+      });
+    self.show_save_image = open;
+  }
+}
+
+fn load_transforms() -> TransformLibrary {
+  let mut lib = TransformLibrary::load(&TRANSFORM_DIRS.map(PathBuf::from));
+  for e in &lib.errors {
+    log::warn!("Couldn't load transform {}: {}", e.path.display(), e.message);
+  }
+  //the default world needs this one even when we're run from somewhere without the transforms/ dir
+  let cube = Transform::cube();
+  if lib.get(&cube.name, &cube.version).is_none() {
+    lib.insert(cube);
+  }
+  lib
 }
 
 impl eframe::App for Display<'_> {
@@ -138,10 +404,16 @@ impl eframe::App for Display<'_> {
   fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
     // TODO: if IFS has updated?
     let new_hash = self.ifs.get_hash();
-    if new_hash != self.ifs_hash {
+    let new_settings_hash = self.ifs.settings_hash();
+    let new_display_hash = self.ifs.display_hash(new_settings_hash);
+    self.world_hashes = (new_hash, new_settings_hash);
+    if new_hash != self.ifs_hash || new_display_hash != self.display_hash {
       println!("hash changed from {} to {}", new_hash, self.ifs_hash);
       match self.engine_pipe().try_send(self.ifs.clone()) {
-        Ok(_) => { self.ifs_hash = new_hash; }
+        Ok(_) => {
+          self.ifs_hash = new_hash;
+          self.display_hash = new_display_hash;
+        }
         Err(_) => {}
       }
     }
@@ -150,6 +422,59 @@ impl eframe::App for Display<'_> {
       self.viewport_texture = tex_id;
       println!("updated viewport texture");
     }
+    if let Some(errors) = self.errors_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+      self.transform_errors = errors;
+    }
+    if let Some(histogram) = self.tonal_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+      self.tonal_histogram = Some(histogram);
+    }
+    if let Some(stats) = self.stats_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+      self.stats = stats;
+    }
+
+    if ctx.input_mut(|i| i.consume_shortcut(&LOAD_SHORTCUT)) {
+      self.load_dialog();
+    }
+    if let Some(status) = self.status_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+      match status {
+        Ok(status) => self.status = Some(status),
+        Err(e) => {
+          self.status = None;
+          self.error_message = Some(format!("{e:#}"));
+        }
+      }
+    }
+
+    //before Ctrl + S, which would take it too
+    if ctx.input_mut(|i| i.consume_shortcut(&SAVE_IMAGE_SHORTCUT)) {
+      self.show_save_image = true;
+    }
+    if ctx.input_mut(|i| i.consume_shortcut(&SAVE_SHORTCUT)) {
+      self.save_dialog();
+    }
+
+    let title = format!("{}{} - IFSRS", if self.is_dirty() { "*" } else { "" }, self.ifs.title);
+    if title != self.window_title {
+      ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+      self.window_title = title;
+    }
+
+    if self.show_save_image {
+      self.save_image_window(ctx);
+    }
+
+    if let Some(msg) = self.error_message.clone() {
+      egui::Window::new("Error")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+          ui.label(msg);
+          if ui.button("OK").clicked() {
+            self.error_message = None;
+          }
+        });
+    }
 
     fn manage_editor<F>(ctx: &egui::Context, name: &'static str, size: [f32; 2], mut editor: F, show: &mut bool)
     where
@@ -172,12 +497,12 @@ impl eframe::App for Display<'_> {
     //If sub-windows are open, draw them
     if self.show_rcurves {
       manage_editor(ctx, "Response Curve Editor", [300.0, 300.0],
-                    || { &mut self.response_curve_editor.ui_content(ctx); },
+                    || { self.response_curve_editor.ui_content(ctx, &mut self.ifs.response_curves, self.tonal_histogram.as_ref()); },
                     &mut self.show_rcurves);
     }
     if self.show_palette {
-      manage_editor(ctx, "Palette Editor", [500.0, 300.0],
-                    || { &mut self.palette_editor.ui_content(ctx); },
+      manage_editor(ctx, "Palette Editor", [500.0, 600.0],
+                    || { self.palette_editor.ui_content(ctx, &mut self.ifs); },
                     &mut self.show_palette);
     }
     if self.show_affines {
@@ -202,9 +527,27 @@ impl eframe::App for Display<'_> {
           if ui.add(widgets::Button::new("New empty world").shortcut_text("Ctrl + N")).clicked() {}
           if ui.add(widgets::Button::new("New random world").shortcut_text("Ctrl + B")).clicked() {}
           ui.separator();
-          if ui.add(widgets::Button::new("Load").shortcut_text("Ctrl + L")).clicked() {}
-          if ui.add(widgets::Button::new("Save").shortcut_text("Ctrl + S")).clicked() {}
-          if ui.add(widgets::Button::new("Save image").shortcut_text("Ctrl + Shift + S")).clicked() {}
+          if ui.add(widgets::Button::new("Load").shortcut_text("Ctrl + L")).clicked() {
+            ui.close_menu();
+            self.load_dialog();
+          }
+          if ui.add(widgets::Button::new("Save").shortcut_text("Ctrl + S")).clicked() {
+            ui.close_menu();
+            self.save_dialog();
+          }
+          if ui.add(widgets::Button::new("Save image").shortcut_text("Ctrl + Shift + S")).clicked() {
+            ui.close_menu();
+            self.show_save_image = true;
+          }
+          ui.separator();
+          if ui.button("Save accumulation").on_hover_text("The raw render, to resume later").clicked() {
+            ui.close_menu();
+            self.save_accumulation_dialog();
+          }
+          if ui.button("Resume accumulation").on_hover_text("Pick several to merge renders of the same world with different seeds").clicked() {
+            ui.close_menu();
+            self.resume_dialog();
+          }
           ui.separator();
           if ui.add(widgets::Button::new("Settings").shortcut_text("Alt + ,")).clicked() {}
           ui.separator();
@@ -266,17 +609,90 @@ impl eframe::App for Display<'_> {
       if ui.button("Add iterator 🤮").clicked() {
         self.automation_editor.update_target(5, "Iterator 0".to_string(), random::<u16>().to_string());
       }
+      ui.separator();
+      ui.horizontal(|ui| {
+        ui.label("Iterators");
+        if ui.add_enabled(!self.ifs.is_full(), egui::Button::new("+")).on_hover_text("Add a connected iterator").clicked() {
+          self.ifs.add_iterator(Iterator::default(), true);
+        }
+      });
+      let mut action = None;
+      let count = self.ifs.iterators.len();
+      let full = self.ifs.is_full();
+      for (i, it) in self.ifs.iterators.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+          let current = format!("{} {}", it.transform.name, it.transform.version);
+          let mut picked = None;
+          egui::ComboBox::from_id_source(("transform", it.id))
+            .selected_text(current)
+            .show_ui(ui, |ui| {
+              for tf in self.transforms.iter() {
+                let selected = *tf == it.transform;
+                if ui.selectable_label(selected, format!("{} {}", tf.name, tf.version)).clicked() && !selected {
+                  picked = Some(tf.clone());
+                }
+              }
+            });
+          if let Some(tf) = picked {
+            it.set_transform(tf);
+          }
+          if ui.add_enabled(i > 0, egui::Button::new("⏶")).clicked() {
+            action = Some(IteratorAction::Move(it.id, i - 1));
+          }
+          if ui.add_enabled(i + 1 < count, egui::Button::new("⏷")).clicked() {
+            action = Some(IteratorAction::Move(it.id, i + 1));
+          }
+          if ui.add_enabled(!full, egui::Button::new("⧉")).on_hover_text("Duplicate, splitting its weight with the copy").clicked() {
+            action = Some(IteratorAction::Duplicate(it.id));
+          }
+          if ui.add_enabled(count > 1, egui::Button::new("🗑")).clicked() {
+            action = Some(IteratorAction::Delete(it.id));
+          }
+        });
+        for e in self.transform_errors.iter().filter(|e| e.iterator_id == Some(it.id)) {
+          ui.colored_label(ui.visuals().error_fg_color, format!("Disabled: {e}"));
+        }
+      }
+      match action {
+        Some(IteratorAction::Move(id, to)) => { self.ifs.reorder_iterator(id, to); }
+        Some(IteratorAction::Duplicate(id)) => { self.ifs.dup_iterator(id, true); }
+        Some(IteratorAction::Delete(id)) => {
+          self.ifs.del_iterator(id);
+          self.automation_editor.remove_target(id);
+        }
+        None => {}
+      }
+      for e in self.transform_errors.iter().filter(|e| e.iterator_id.is_none()) {
+        ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+      }
     });
 
     egui::SidePanel::right("right_panel").resizable(false).show(ctx, |ui| {
+      //what's rendered and saved, the viewport just shows it scaled to fit
       ui.horizontal(|ui| {
         ui.label("Image dimensions: ");
+        let (width, height) = (self.ifs.width, self.ifs.height);
         integer_edit_field(ui, &mut self.ifs.width);
-        integer_edit_field(ui, &mut self.ifs.height); //TODO--IMPLEMENT ASPECT RATIO LOCKING
+        integer_edit_field(ui, &mut self.ifs.height);
+        if self.lock_aspect_ratio {
+          let aspect = *self.aspect_ratio.get_or_insert(width as f64 / height.max(1) as f64);
+          if self.ifs.width != width {
+            self.ifs.height = ((self.ifs.width as f64 / aspect).round() as u32).clamp(1, 4096);
+          } else if self.ifs.height != height {
+            self.ifs.width = ((self.ifs.height as f64 * aspect).round() as u32).clamp(1, 4096);
+          }
+        }
       });
       ui.horizontal(|ui| {
         ui.label("Lock aspect ratio? ");
-        ui.checkbox(&mut self.lock_aspect_ratio, "");
+        if ui.checkbox(&mut self.lock_aspect_ratio, "").changed() {
+          self.aspect_ratio = None;
+        }
+        if ui.button("Fit viewport").on_hover_text("Render at the size the viewport is now").clicked() {
+          self.ifs.width = (self.viewport.width as u32).clamp(1, 4096);
+          self.ifs.height = (self.viewport.height as u32).clamp(1, 4096);
+          self.aspect_ratio = None;
+        }
       });
       ui.separator();
       ui.horizontal(|ui| {
@@ -328,7 +744,7 @@ impl eframe::App for Display<'_> {
       ui.separator();
       ui.horizontal(|ui| {
         ui.label("Stopping SL: ");
-        ui.checkbox(&mut self.use_stopping_sl, "");
+        ui.checkbox(&mut self.ifs.use_stopping_sl, "");
         ui.add(egui::DragValue::new(&mut self.ifs.stopping_sl).speed(0.01).clamp_range(0..=UPPER_BOUND));
       });
       ui.horizontal(|ui| {
@@ -341,15 +757,29 @@ impl eframe::App for Display<'_> {
         ui.label("Pause rendering? ");
         ui.checkbox(&mut self.ifs.pause_rendering, "");
       });
+      ui.horizontal(|ui| {
+        ui.label("Sampling level: ");
+        if self.stats.sampling_level.is_finite() {
+          ui.label(format!("{:.2}", self.stats.sampling_level));
+        } else {
+          ui.label("-");
+        }
+        if self.stats.stopped {
+          ui.label(if self.ifs.pause_rendering { "(paused)" } else { "(done)" });
+        }
+      });
+      ui.horizontal(|ui| {
+        ui.label("Iterations/s: ");
+        ui.label(format!("{:.1}M", self.stats.iterations_per_second / 1e6));
+      });
+      if let Some(status) = &self.status {
+        ui.label(status);
+      }
     });
 
     egui::CentralPanel::default().frame(Frame::none()).show(ctx, |ui| {
-      self.ifs.width = ui.available_width() as u32;
-      self.ifs.height = ui.available_height() as u32;
-      self.viewport.ui_content(ui, self.viewport_texture);
+      self.viewport.ui_content(ui, self.viewport_texture, [self.ifs.width, self.ifs.height]);
       self.ifs.camera.translate(self.viewport.pos_delta);
-
-      // TODO: track resizes and send a size message
     });
 
     ctx.request_repaint();
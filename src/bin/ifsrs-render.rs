@@ -0,0 +1,280 @@
+//! Renders a world to an image with no window, for scripting renders on machines without a display.
+//! Works on software adapters like lavapipe, see --list-adapters.
+
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Context, Result};
+use IFSRS::model::ifs::IFS;
+use IFSRS::model::transform_library::TransformLibrary;
+use IFSRS::rendering::accumulation::Accumulation;
+use IFSRS::rendering::gpu_renderer::{Gpu, GpuRenderer};
+use IFSRS::rendering::graphics_engine::RESPONSE_CURVE_SIZE;
+use IFSRS::rendering::image_export::{save_image, tone_map_image, ImageFormat};
+
+const USAGE: &str = "\
+Usage: ifsrs-render [OPTIONS] <WORLD>
+
+Renders a world (.toml, or an IFSRenderer .ifsjson) to a PNG or EXR.
+
+Options:
+  -o, --output <PATH>       Image to write [default: the world's name, as .png or .exr]
+      --format <FORMAT>     png8, png16 or exr (32-bit float) [default: from the output's extension, else png8]
+      --alpha               Leave the background out, transparent
+      --width <PX>          Override the world's width
+      --height <PX>         Override the world's height
+      --seed <N>            Seed for the kernel's random numbers
+      --sl <LEVEL>          Stop at this sampling level, log2 of samples that landed per pixel
+                            [default: the world's stopping_sl, unless --time is given]
+      --time <SECONDS>      Stop after this long
+      --resume <PATH>       Carry on from a saved accumulation, with its seed. Given more than once,
+                            renders of the same world with different seeds are merged first
+      --save-accumulation <PATH>
+                            Also save the raw accumulation, to resume or merge later
+      --adapter <ADAPTER>   Index or part of the name of the adapter to use, e.g. llvmpipe
+      --list-adapters       List adapters and exit
+      --transforms <DIR>    Where to find transforms for .ifsjson worlds, can be repeated [default: transforms]
+      --help                Show this and exit";
+
+#[derive(Default)]
+struct Args {
+    world: Option<PathBuf>,
+    output: Option<PathBuf>,
+    format: Option<String>,
+    alpha: bool,
+    width: Option<u32>,
+    height: Option<u32>,
+    seed: Option<u32>,
+    sl: Option<f64>,
+    time: Option<f64>,
+    resume: Vec<PathBuf>,
+    save_accumulation: Option<PathBuf>,
+    adapter: Option<String>,
+    list_adapters: bool,
+    transforms: Vec<PathBuf>,
+    help: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    fn value<T: std::str::FromStr>(flag: &str, v: Option<String>) -> Result<T> {
+        let v = v.ok_or_else(|| anyhow!("{flag} needs a value"))?;
+        v.parse().map_err(|_| anyhow!("Invalid value for {flag}: {v}"))
+    }
+
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => parsed.output = Some(value(&arg, args.next())?),
+            "--format" => parsed.format = Some(value(&arg, args.next())?),
+            "--alpha" => parsed.alpha = true,
+            "--width" => parsed.width = Some(value(&arg, args.next())?),
+            "--height" => parsed.height = Some(value(&arg, args.next())?),
+            "--seed" => parsed.seed = Some(value(&arg, args.next())?),
+            "--sl" => parsed.sl = Some(value(&arg, args.next())?),
+            "--time" => parsed.time = Some(value(&arg, args.next())?),
+            "--resume" => parsed.resume.push(value(&arg, args.next())?),
+            "--save-accumulation" => parsed.save_accumulation = Some(value(&arg, args.next())?),
+            "--adapter" => parsed.adapter = Some(value(&arg, args.next())?),
+            "--transforms" => parsed.transforms.push(value(&arg, args.next())?),
+            "--list-adapters" => parsed.list_adapters = true,
+            "-h" | "--help" => parsed.help = true,
+            flag if flag.starts_with('-') => bail!("Unknown option {flag}"),
+            _ if parsed.world.is_none() => parsed.world = Some(PathBuf::from(arg)),
+            _ => bail!("Only one world can be rendered at a time"),
+        }
+    }
+    Ok(parsed)
+}
+
+fn load_world(path: &Path, transform_dirs: &[PathBuf]) -> Result<IFS> {
+    if path.extension().is_some_and(|e| e == "ifsjson" || e == "json") {
+        let lib = TransformLibrary::load(transform_dirs);
+        for e in &lib.errors {
+            log::warn!("Couldn't load transform {}: {}", e.path.display(), e.message);
+        }
+        IFS::load_ifsjson(path, |name, version| lib.get(name, version).cloned())
+    } else {
+        IFS::load_world(path)
+    }
+}
+
+fn parse_format(name: &str) -> Result<ImageFormat> {
+    match name {
+        "png8" => Ok(ImageFormat::Png8),
+        "png16" => Ok(ImageFormat::Png16),
+        "exr" => Ok(ImageFormat::Exr),
+        _ => bail!("Unknown format {name}, expected png8, png16 or exr"),
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    if args.list_adapters {
+        for (i, info) in Gpu::adapters().iter().enumerate() {
+            println!("{i}: {} ({:?}, {:?})", info.name, info.device_type, info.backend);
+        }
+        return Ok(());
+    }
+    let Some(world_path) = args.world else {
+        bail!("No world given\n\n{USAGE}");
+    };
+    let transform_dirs = if args.transforms.is_empty() { vec![PathBuf::from("transforms")] } else { args.transforms };
+    let mut ifs = load_world(&world_path, &transform_dirs)?;
+    ifs.width = args.width.unwrap_or(ifs.width);
+    ifs.height = args.height.unwrap_or(ifs.height);
+    if ifs.width == 0 || ifs.height == 0 {
+        bail!("Can't render at {}x{}", ifs.width, ifs.height);
+    }
+    let (width, height) = (ifs.width, ifs.height);
+    let target_sl = args.sl.or(if args.time.is_some() { None } else { Some(ifs.stopping_sl as f64) });
+    let time_limit = args.time.map(Duration::from_secs_f64);
+    let format = match &args.format {
+        Some(name) => parse_format(name)?,
+        None => args.output.as_deref().and_then(ImageFormat::from_path).unwrap_or(ImageFormat::Png8),
+    };
+    let output = args.output.unwrap_or_else(|| world_path.with_extension(format.extension()));
+
+    let gpu = Gpu::headless(args.adapter.as_deref())?;
+    let mut renderer = GpuRenderer::new(&gpu);
+    if let Some(seed) = args.seed {
+        renderer.seed = seed;
+    }
+    let errors = renderer.update_model(&gpu, ifs);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter()
+            .map(|e| match e.iterator_id {
+                Some(id) => format!("Iterator {id}: {e}"),
+                None => e.to_string(),
+            })
+            .collect();
+        bail!("The world can't be rendered as it is:\n{}", errors.join("\n"));
+    }
+    if !args.resume.is_empty() {
+        let acc = Accumulation::load_merged(&args.resume)?;
+        renderer.resume(&gpu, &acc).context("Couldn't resume")?;
+        eprintln!("Resumed at sampling level {:.2}", renderer.sampling_level());
+    }
+
+    let start = Instant::now();
+    render(&gpu, &mut renderer, target_sl, time_limit)?;
+    eprintln!("Rendered {} iterations in {:.1}s, sampling level {:.2}",
+        renderer.iterations(), start.elapsed().as_secs_f64(), renderer.sampling_level());
+
+    if let Some(path) = &args.save_accumulation {
+        let (readback, mut acc) = renderer.read_accumulation(&gpu);
+        gpu.device.poll(wgpu::Maintain::Wait);
+        acc.fill(&readback.try_take().unwrap_or_else(|| Err(anyhow!("The accumulation never finished copying")))?)?;
+        acc.save(path)?;
+        eprintln!("Wrote {}", path.display());
+    }
+
+    let readback = renderer.read_histogram(&gpu);
+    gpu.device.poll(wgpu::Maintain::Wait);
+    let bytes = readback.try_take().unwrap_or_else(|| Err(anyhow!("The histogram never finished copying")))?;
+    let histogram: Vec<[f32; 4]> = bytes.chunks_exact(size_of::<[f32; 4]>()).map(bytemuck::pod_read_unaligned).collect();
+    let curves = renderer.model().response_curves.bake(RESPONSE_CURVE_SIZE);
+    let pixels = tone_map_image(&histogram, &renderer.tone_map(), &curves, format, args.alpha);
+    save_image(&output, format, width, height, &pixels, args.alpha)?;
+    eprintln!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Dispatches after which a render with nothing landed in the image gives up, its level would stay at -inf
+const EMPTY_DISPATCH_LIMIT: i32 = 64;
+
+/// Dispatches until the sampling level reaches `target_sl` or `time_limit` has passed
+fn render(gpu: &Gpu, renderer: &mut GpuRenderer, target_sl: Option<f64>, time_limit: Option<Duration>) -> Result<()> {
+    let start = Instant::now();
+    let mut last_report = start;
+    loop {
+        renderer.dispatch(gpu);
+        gpu.device.poll(wgpu::Maintain::Wait);
+        renderer.update_accepted_samples();
+
+        let level = renderer.sampling_level();
+        let elapsed = start.elapsed();
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            eprintln!("{:.1}s: sampling level {level:.2}", elapsed.as_secs_f64());
+            last_report = Instant::now();
+        }
+        if target_sl.is_some_and(|sl| level >= sl) || time_limit.is_some_and(|t| elapsed >= t) {
+            return Ok(());
+        }
+        if renderer.accepted_samples() == 0 && renderer.dispatch_count() >= EMPTY_DISPATCH_LIMIT {
+            bail!("Nothing landed in the image after {} iterations, check the camera and the iterators' opacity",
+                renderer.iterations());
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) if args.help => {
+            println!("{USAGE}");
+            return;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["world.toml", "-o", "out.exr", "--alpha", "--width", "640", "--sl", "12.5",
+            "--resume", "a.acc", "--resume", "b.acc", "--transforms", "mine"]).unwrap();
+        assert_eq!(args.world, Some(PathBuf::from("world.toml")));
+        assert_eq!(args.output, Some(PathBuf::from("out.exr")));
+        assert!(args.alpha);
+        assert_eq!(args.width, Some(640));
+        assert_eq!(args.height, None);
+        assert_eq!(args.sl, Some(12.5));
+        assert_eq!(args.resume, [PathBuf::from("a.acc"), PathBuf::from("b.acc")]);
+        assert_eq!(args.transforms, [PathBuf::from("mine")]);
+        assert!(!args.help && !args.list_adapters);
+        assert!(parse(&["--help"]).unwrap().help);
+
+        let error = |args: &[&str]| parse(args).err().map(|e| e.to_string());
+        assert_eq!(error(&["--frobnicate"]).as_deref(), Some("Unknown option --frobnicate"));
+        assert_eq!(error(&["a.toml", "b.toml"]).as_deref(), Some("Only one world can be rendered at a time"));
+        assert_eq!(error(&["--width"]).as_deref(), Some("--width needs a value"));
+        assert_eq!(error(&["--width", "wide"]).as_deref(), Some("Invalid value for --width: wide"));
+        assert_eq!(error(&["--seed", "-1"]).as_deref(), Some("Invalid value for --seed: -1"));
+    }
+
+    #[test]
+    fn test_render_gives_up_when_nothing_lands() {
+        let Ok(gpu) = Gpu::headless(None) else {
+            eprintln!("No adapter, so nothing to render with");
+            return;
+        };
+        let mut ifs = IFS::cube_example();
+        ifs.width = 32;
+        ifs.height = 24;
+        for it in &mut ifs.iterators {
+            it.opacity = 0.0;
+        }
+        let mut renderer = GpuRenderer::new(&gpu);
+        renderer.invocation_iters = 16;
+        assert!(renderer.update_model(&gpu, ifs).is_empty());
+
+        let error = render(&gpu, &mut renderer, Some(1.0), None).unwrap_err();
+        assert!(error.to_string().starts_with("Nothing landed in the image"), "{error}");
+        assert_eq!(renderer.dispatch_count(), EMPTY_DISPATCH_LIMIT);
+        assert_eq!(renderer.accepted_samples(), 0);
+    }
+}
@@ -270,14 +270,7 @@ impl AutomationEditor {
       if let Some(block) = delete_block {
         //refactor if we ever decouple opening the block context menu from selecting
         self.selected_block = None;
-        for (_, t) in self.blocks[block].get_terminals() {
-          let d: Vec<EdgeIndex> = self.graph.edges(t).map(|e| e.id()).collect();
-          for a in d {
-            self.graph.remove_edge(a);
-          }
-          self.graph.remove_node(t);
-        }
-        self.blocks.remove(block);
+        self.remove_block(block);
       }
       //ONLY DISPLAYS SHOULD END UP IN HERE. ITERATORS CALL PROCESS FROM OUTSIDE
 
@@ -315,6 +308,46 @@ impl AutomationEditor {
     }
     return id;
   }
+  /// # Remove Block
+  /// Removes a block along with its terminals and everything connected to them
+  fn remove_block(&mut self, block: BlockId) {
+    for (_, t) in self.blocks[block].get_terminals() {
+      let d: Vec<EdgeIndex> = self.graph.edges(t).map(|e| e.id()).collect();
+      for a in d {
+        self.graph.remove_edge(a);
+      }
+      self.graph.remove_node(t);
+    }
+    self.blocks.remove(block);
+  }
+
+  /// # Target
+  /// The target block for an iterator, if it's automated
+  pub fn target(&self, it_id: i32) -> Option<BlockId> {
+    self.blocks
+      .iter()
+      .find(|(_, b)| matches!(b.block_type, BlockType::TARGET(TargetType::ITERATOR(id)) if id == it_id))
+      .map(|(id, _)| id)
+  }
+
+  /// # Has Terminal
+  /// Whether a terminal, e.g. one update_target handed out, is still in the graph
+  pub fn has_terminal(&self, term: NodeIndex) -> bool {
+    self.graph.contains_node(term)
+  }
+
+  /// # Remove Target
+  /// Removes the target block for an iterator, for when the iterator is deleted.
+  /// Does nothing if it was never automated.
+  pub fn remove_target(&mut self, it_id: i32) {
+    if let Some(block) = self.target(it_id) {
+      if self.selected_block == Some(block) {
+        self.selected_block = None;
+      }
+      self.remove_block(block);
+    }
+  }
+
   //TODO--Enable removing terminals
   fn update_block(
     b: &mut Block,
@@ -345,8 +378,8 @@ impl AutomationEditor {
     //Iterator block exists, find the block and update it
     if let Some((id, b)) = self.blocks
       .iter_mut()
-      .find(|(id, b)|
-      { matches!(b.block_type, BlockType::TARGET(TargetType::ITERATOR(it_id))) })
+      .find(|(_, b)|
+      { matches!(b.block_type, BlockType::TARGET(TargetType::ITERATOR(id)) if id == it_id) })
     {
       AutomationEditor::update_block(
         b,
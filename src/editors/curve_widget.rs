@@ -0,0 +1,89 @@
+//! A curve through draggable control points, shared by the editors that need one.
+//! Points are in [0,1] with y flipped like the screen, so y = 0 is the top, and they're kept sorted by x.
+
+use eframe::emath;
+use eframe::emath::{Pos2, pos2, Rect, Vec2, vec2};
+use eframe::epaint::{Shape, Stroke};
+use egui::{Painter, Sense, Ui};
+
+const CONTROL_POINT_RADIUS: f32 = 6.0;
+const LINE_CLICK_RADIUS: f32 = 8.0;
+
+/// One curve: drag points around, click the line to add one, right click a point to remove it.
+/// `background` paints under the curve, over a plain fill.
+/// Returns whether an edit started this frame, and whether the points changed.
+pub fn curve_ui(ui: &mut Ui, size: Vec2, points: &mut Vec<Pos2>, stroke: Stroke,
+                background: impl FnOnce(&Painter, Rect)) -> (bool, bool) {
+    let (response, painter) = ui.allocate_painter(size, Sense::click());
+    let to_screen = emath::RectTransform::from_to(
+        Rect::from_min_size(Pos2::ZERO, vec2(1.0, 1.0)),
+        response.rect,
+    );
+    let scale = vec2(1.0 / response.rect.width(), 1.0 / response.rect.height());
+    painter.rect_filled(response.rect, 0.0, ui.visuals().extreme_bg_color);
+    background(&painter, response.rect);
+
+    let mut started = false;
+    let mut changed = false;
+    let mut remove = None;
+    let mut control_point_shapes = vec![];
+    let last = points.len() - 1;
+    for i in 0..points.len() {
+        let point_rect = Rect::from_center_size(to_screen * points[i], Vec2::splat(2.0 * CONTROL_POINT_RADIUS));
+        let point_response = ui.interact(point_rect, response.id.with(i), Sense::click_and_drag());
+        started |= point_response.drag_started();
+
+        let delta = point_response.drag_delta();
+        if delta != Vec2::ZERO {
+            let mut new_point = to_screen.from().clamp(points[i] + delta * scale);
+            // endpoints stay on the edges, and nothing passes its neighbours so the curve stays a function
+            new_point.x = match i {
+                0 => 0.0,
+                i if i == last => 1.0,
+                i => new_point.x.clamp(points[i - 1].x.min(points[i + 1].x), points[i - 1].x.max(points[i + 1].x)),
+            };
+            points[i] = new_point;
+            changed = true;
+        }
+        if point_response.secondary_clicked() && i != 0 && i != last {
+            remove = Some(i);
+        }
+        let stroke = ui.style().interact(&point_response).fg_stroke;
+        control_point_shapes.push(Shape::circle_stroke(to_screen * points[i], CONTROL_POINT_RADIUS, stroke));
+    }
+
+    if let Some(i) = remove {
+        points.remove(i);
+        started = true;
+        changed = true;
+    } else if response.clicked() {
+        if let Some(click) = response.interact_pointer_pos() {
+            let p = to_screen.inverse() * click;
+            let on_line = to_screen * pos2(p.x, 1.0 - eval_curve(points, p.x));
+            if on_line.distance(click) < LINE_CLICK_RADIUS {
+                let i = points.iter().position(|q| q.x > p.x).unwrap_or(last).max(1);
+                points.insert(i, p);
+                started = true;
+                changed = true;
+            }
+        }
+    }
+
+    let line: Vec<Pos2> = points.iter().map(|p| to_screen * *p).collect();
+    painter.add(Shape::line(line, stroke));
+    painter.extend(control_point_shapes);
+    (started, changed)
+}
+
+/// The curve's value at `x`, linear between its points. Up is 1.
+pub fn eval_curve(points: &[Pos2], x: f32) -> f32 {
+    let y = match points.iter().position(|p| p.x >= x) {
+        None => points.last().map_or(0.5, |p| p.y),
+        Some(0) => points[0].y,
+        Some(i) => {
+            let (a, b) = (points[i - 1], points[i]);
+            if b.x > a.x { a.y + (b.y - a.y) * (x - a.x) / (b.x - a.x) } else { b.y }
+        }
+    };
+    1.0 - y
+}
@@ -1,5 +1,6 @@
 pub mod response_curve_editor;
 pub mod palette_editor;
+pub mod curve_widget;
 pub mod affine_editor;
 pub mod weight_graph_editor;
 pub mod animation_editor;
@@ -1,16 +1,63 @@
-use eframe::emath;
-use eframe::emath::{Pos2, pos2, Rect, Vec2, vec2};
-use eframe::epaint::{Color32, Stroke};
-use egui::{Context, Sense, Ui};
+use eframe::emath::{Pos2, pos2, Rect, vec2};
+use eframe::epaint::{Color32, Shape, Stroke};
+use egui::{Context, Key, KeyboardShortcut, Modifiers, Sense, Ui};
+use strum::IntoEnumIterator;
+use crate::model::ifs::IFS;
+use crate::model::palette::Palette;
+use crate::model::palette_generation::{generate, smooth, sort_by_lightness, Scheme};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::palette_generation::from_image;
+use crate::rendering::graphics_engine::MAX_PALETTE_COLORS;
+use crate::editors::curve_widget::{curve_ui, eval_curve};
+use crate::util::math_extensions::hsv_to_rgb;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::palette_import::load_palettes;
 
-pub struct PaletteEditor{
-    palette: [u8; 256*3],
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+const MAX_UNDO: usize = 100;
+const CURVE_HEIGHT: f32 = 70.0;
+/// What the world's palette is called once the curves have written to it
+const HSV_PALETTE_NAME: &str = "HSV curves";
+/// How many colors either side Smooth averages over
+const SMOOTH_RADIUS: usize = 4;
+const MARKER_HEIGHT: f32 = 16.0;
+const MARKER_MIN_HALF_WIDTH: f32 = 3.0;
+const MARKER_MAX_HALF_WIDTH: f32 = 14.0;
+/// Pixels of dragging up to go from no color_speed to all of it
+const SPEED_DRAG_DISTANCE: f32 = 100.0;
+/// Points of scrolling to go from transparent to opaque
+const OPACITY_SCROLL_DISTANCE: f32 = 200.0;
+
+/// Everything an undo puts back
+#[derive(Clone)]
+struct Snapshot {
     points_h: Vec<Pos2>,
     points_s: Vec<Pos2>,
     points_v: Vec<Pos2>,
+    palette: Palette,
+    coloring: Vec<(i32, [f32; 3])>, //iterator id, and its color_index, color_speed and opacity
+}
+
+pub struct PaletteEditor{
+    palette: [u8; 256*3], //the curves, baked
+    points_h: Vec<Pos2>, //sorted by x, y is flipped like the screen so 0 is the top
+    points_s: Vec<Pos2>,
+    points_v: Vec<Pos2>,
     stroke_h: Stroke,
     stroke_s: Stroke,
     stroke_v: Stroke,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    scrolling: bool, //over a marker last frame, so a scroll is one undo and not one per frame
+    scheme: Scheme,
+    seed: u64,
+    image_colors: usize, //clusters to pull out of an image
+    /// Palettes from the last file loaded, to pick from
+    library: Vec<Palette>,
+    library_name: String,
+    error: Option<String>, //from the last palette file or image loaded
+    filter: String,
 }
 
 impl Default for PaletteEditor {
@@ -21,42 +68,340 @@ impl Default for PaletteEditor {
                                  pos2(0.75,0.5),
                                  pos2(1.0,0.5)];
         Self {
+            palette: bake_hsv_curves(&default_curve, &default_curve, &default_curve),
             points_h: default_curve.clone(),
             points_s: default_curve.clone(),
             points_v: default_curve,
             stroke_h: Stroke::new(2.0, Color32::LIGHT_GREEN.linear_multiply(0.25)),
             stroke_s: Stroke::new(2.0, Color32::LIGHT_BLUE.linear_multiply(0.25)),
             stroke_v: Stroke::new(2.0, Color32::LIGHT_YELLOW.linear_multiply(0.25)),
-            palette: [0; 256*3]
+            undo: vec![],
+            redo: vec![],
+            scrolling: false,
+            scheme: Scheme::Cosine,
+            seed: 0,
+            image_colors: 6,
+            library: vec![],
+            library_name: String::new(),
+            error: None,
+            filter: String::new(),
         }
     }
 }
 
-///TODO
-/// This editor should have three HSV curves stacked vertically that allow you to define a palette.
-/// There should also be some functionality for randomly generating palettes, smoothing them, and importing them.
-/// This defines the palette that each iterator has a position on, described as a float from [0,1].
-/// We would also like to have some indication in the window that points to where each iterator lives in the palatte,
-/// as well as the ability to drag those values left/right to set color position, up/down to set color speed,
-/// and scroll up/down to change opacity. (If we imagine a triangle pointing at a position on the palette,
-/// the triangle would become wider as we increase color speed, and hollower (to a wireframe outline) as we adjust opacity.
+/// Three HSV curves stacked vertically define a palette, which replaces the world's as soon as they're edited.
+/// Palettes can also be generated from a seed, pulled out of an image, or picked out of .ugr, .map and .gpl files.
+/// Each iterator has a position on the palette, its color_index in [0,1], marked with a triangle under the strip.
+/// Edits here have their own undo history.
 impl PaletteEditor {
-    pub fn ui_content(&mut self, ctx: &Context) {
+    pub fn ui_content(&mut self, ctx: &Context, ifs: &mut IFS) {
+        if ctx.input_mut(|i| i.consume_shortcut(&REDO_SHORTCUT)) {
+            self.redo(ifs);
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT)) {
+            self.undo(ifs);
+        }
+        let before = self.snapshot(ifs);
         egui::CentralPanel::default().show(ctx, |ui| {
-            let (response, _painter) =
-                ui.allocate_painter(Vec2::new(ui.available_width(), 300.0), Sense::hover());
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!self.undo.is_empty(), egui::Button::new("Undo")).clicked() {
+                    self.undo(ifs);
+                }
+                if ui.add_enabled(!self.redo.is_empty(), egui::Button::new("Redo")).clicked() {
+                    self.redo(ifs);
+                }
+                ui.separator();
+                ui.label(&ifs.palette.name);
+                let rotation = ui.add(egui::DragValue::new(&mut ifs.palette.rotation).prefix("rotation: "));
+                if rotation.drag_started() || (rotation.changed() && !rotation.dragged()) {
+                    self.push_undo(before.clone());
+                }
+            });
+            let (rect, _) = ui.allocate_exact_size(vec2(ui.available_width(), 24.0), Sense::hover());
+            paint_palette(ui, rect, &ifs.palette);
+            self.markers_ui(ui, rect, ifs, &before);
+
+            let mut started = false;
+            let mut changed = false;
+            for (label, points, stroke) in [("Hue", &mut self.points_h, self.stroke_h),
+                                            ("Saturation", &mut self.points_s, self.stroke_s),
+                                            ("Value", &mut self.points_v, self.stroke_v)] {
+                ui.label(label);
+                //the strokes are kept faint, the line is drawn at full strength
+                let line = Stroke::new(stroke.width, stroke.color.to_opaque());
+                let (s, c) = curve_ui(ui, vec2(ui.available_width(), CURVE_HEIGHT), points, line, |_, _| {});
+                started |= s;
+                changed |= c;
+            }
+            if started {
+                self.push_undo(before.clone());
+            }
+            if changed {
+                self.write_palette(&mut ifs.palette);
+            }
+            let (rect, _) = ui.allocate_exact_size(vec2(ui.available_width(), 16.0), Sense::hover());
+            let painter = ui.painter_at(rect);
+            let width = rect.width() / 256.0;
+            for (i, c) in self.palette.chunks_exact(3).enumerate() {
+                let min = pos2(rect.min.x + i as f32 * width, rect.min.y);
+                painter.rect_filled(Rect::from_min_size(min, vec2(width + 0.5, rect.height())), 0.0,
+                                    Color32::from_rgb(c[0], c[1], c[2]));
+            }
 
-            let _to_screen = emath::RectTransform::from_to(
-                Rect::from_min_size(Pos2::ZERO, vec2(1.0, 1.0)),
-                response.rect,
-            );
-            ui.label("One day, I'm going to be a real window!");
+            ui.separator();
+            self.generator_ui(ui, &mut ifs.palette, &before);
+            ui.separator();
+            self.library_ui(ui, &mut ifs.palette, &before);
+        });
+    }
 
-            let xwidth = response.rect.max[0] - response.rect.min[0];
-            let ywidth = response.rect.max[1] - response.rect.min[1];
-            let _scale: Vec2 = vec2(1.0 / xwidth, 1.0 / ywidth);
+    /// A triangle under `strip` for each iterator, pointing at its color_index. Dragging sideways moves it,
+    /// dragging up and down sets color_speed from -1 to 1 (wider is faster, negative speeds are as narrow as 0), and scrolling sets opacity (hollower is more transparent).
+    fn markers_ui(&mut self, ui: &mut Ui, strip: Rect, ifs: &mut IFS, before: &Snapshot) {
+        let (rect, _) = ui.allocate_exact_size(vec2(strip.width(), MARKER_HEIGHT), Sense::hover());
+        let painter = ui.painter();
+        let colors = ifs.palette.resample(MAX_PALETTE_COLORS);
+        let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+        let mut started = false;
+        let mut scrolled = false;
+        for it in ifs.iterators.iter_mut() {
+            let x = rect.left() + it.color_index.clamp(0.0, 1.0) * rect.width();
+            let half_width = MARKER_MIN_HALF_WIDTH
+                + it.color_speed.clamp(0.0, 1.0) * (MARKER_MAX_HALF_WIDTH - MARKER_MIN_HALF_WIDTH);
+            let hit = Rect::from_min_max(pos2(x - half_width, rect.top()), pos2(x + half_width, rect.bottom()));
+            let response = ui.interact(hit, ui.id().with(("marker", it.id)), Sense::drag());
+            started |= response.drag_started();
+
+            //each direction only touches its own value, so a sideways drag leaves a speed outside 0..1 alone
+            let delta = response.drag_delta();
+            if delta.x != 0.0 {
+                it.color_index = (it.color_index + delta.x / rect.width()).clamp(0.0, 1.0);
+            }
+            if delta.y != 0.0 {
+                it.color_speed = (it.color_speed - delta.y / SPEED_DRAG_DISTANCE).clamp(-1.0, 1.0);
+            }
+            if response.hovered() && scroll != 0.0 {
+                scrolled = true;
+                it.opacity = (it.opacity + scroll / OPACITY_SCROLL_DISTANCE).clamp(0.0, 1.0);
+            }
+
+            let c = colors[(it.color_index.clamp(0.0, 1.0) * (colors.len() - 1) as f32).round() as usize];
+            let to8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+            let fill = Color32::from_rgba_unmultiplied(to8(c[0]), to8(c[1]), to8(c[2]), to8(it.opacity));
+            let triangle = vec![pos2(x, rect.top()),
+                                pos2(x + half_width, rect.bottom()),
+                                pos2(x - half_width, rect.bottom())];
+            painter.add(Shape::convex_polygon(triangle, fill, ui.style().interact(&response).fg_stroke));
+            let name = if it.name.is_empty() { format!("Iterator {}", it.id) } else { it.name.clone() };
+            response.on_hover_text(format!("{name}\ncolor index {:.3}\ncolor speed {:.3}\nopacity {:.3}",
+                                           it.color_index, it.color_speed, it.opacity));
+        }
+        if started || (scrolled && !self.scrolling) {
+            self.push_undo(before.clone());
+        }
+        self.scrolling = scrolled;
+    }
+
+    fn snapshot(&self, ifs: &IFS) -> Snapshot {
+        Snapshot {
+            points_h: self.points_h.clone(),
+            points_s: self.points_s.clone(),
+            points_v: self.points_v.clone(),
+            palette: ifs.palette.clone(),
+            coloring: ifs.iterators.iter()
+                .map(|it| (it.id, [it.color_index, it.color_speed, it.opacity]))
+                .collect(),
+        }
+    }
+
+    /// Iterators added since the snapshot are left as they are
+    fn restore(&mut self, snapshot: Snapshot, ifs: &mut IFS) {
+        self.points_h = snapshot.points_h;
+        self.points_s = snapshot.points_s;
+        self.points_v = snapshot.points_v;
+        self.palette = bake_hsv_curves(&self.points_h, &self.points_s, &self.points_v);
+        ifs.palette = snapshot.palette;
+        for (id, [color_index, color_speed, opacity]) in snapshot.coloring {
+            if let Some(it) = ifs.iterators.iter_mut().find(|it| it.id == id) {
+                it.color_index = color_index;
+                it.color_speed = color_speed;
+                it.opacity = opacity;
+            }
+        }
+    }
+
+    /// Call with the state from before an edit, as the edit starts
+    fn push_undo(&mut self, snapshot: Snapshot) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(snapshot);
+        self.redo.clear();
+    }
+
+    fn undo(&mut self, ifs: &mut IFS) {
+        if let Some(snapshot) = self.undo.pop() {
+            self.redo.push(self.snapshot(ifs));
+            self.restore(snapshot, ifs);
+        }
+    }
 
-            response
+    fn redo(&mut self, ifs: &mut IFS) {
+        if let Some(snapshot) = self.redo.pop() {
+            self.undo.push(self.snapshot(ifs));
+            self.restore(snapshot, ifs);
+        }
+    }
+
+    /// Bake the curves and make them the world's palette, keeping its rotation
+    fn write_palette(&mut self, palette: &mut Palette) {
+        self.palette = bake_hsv_curves(&self.points_h, &self.points_s, &self.points_v);
+        palette.name = String::from(HSV_PALETTE_NAME);
+        palette.colors = self.palette.chunks_exact(3)
+            .map(|c| [c[0] as f32 / 255.0, c[1] as f32 / 255.0, c[2] as f32 / 255.0, 1.0])
+            .collect();
+    }
+
+    /// Make a palette from a seed or an image, or rework the one there is
+    fn generator_ui(&mut self, ui: &mut Ui, palette: &mut Palette, before: &Snapshot) {
+        let mut new_palette = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("scheme")
+                .selected_text(self.scheme.name())
+                .show_ui(ui, |ui| {
+                    for scheme in Scheme::iter() {
+                        ui.selectable_value(&mut self.scheme, scheme, scheme.name());
+                    }
+                });
+            ui.add(egui::DragValue::new(&mut self.seed).prefix("seed: "));
+            if ui.button("Generate").clicked() {
+                new_palette = Some(generate(self.scheme, self.seed));
+            }
+            if ui.button("Random").on_hover_text("Generate with a new seed").clicked() {
+                self.seed = rand::random();
+                new_palette = Some(generate(self.scheme, self.seed));
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Smooth").clicked() {
+                new_palette = Some(smooth(palette, SMOOTH_RADIUS));
+            }
+            if ui.button("Sort by lightness").clicked() {
+                new_palette = Some(sort_by_lightness(palette));
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                ui.separator();
+                ui.add(egui::DragValue::new(&mut self.image_colors).clamp_range(1..=32).suffix(" colors"));
+                if ui.button("From image…").on_hover_text("The image's main colors, using the seed").clicked() {
+                    new_palette = self.image_dialog();
+                }
+            }
+        });
+        if let Some(p) = new_palette {
+            self.push_undo(before.clone());
+            *palette = p;
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn image_dialog(&mut self) -> Option<Palette> {
+        let path = rfd::FileDialog::new().add_filter("PNG image", &["png"]).pick_file()?;
+        match from_image(&path, self.image_colors, self.seed) {
+            Ok(palette) => {
+                self.error = None;
+                Some(palette)
+            }
+            Err(e) => {
+                self.error = Some(format!("{e:#}"));
+                None
+            }
+        }
+    }
+
+    /// Pick a palette out of a .ugr, .map or .gpl file
+    fn library_ui(&mut self, ui: &mut Ui, palette: &mut Palette, before: &Snapshot) {
+        ui.horizontal(|ui| {
+            #[cfg(not(target_arch = "wasm32"))]
+            if ui.button("Load palettes…").clicked() {
+                self.load_dialog();
+            }
+            ui.label(&self.library_name);
+            ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("filter").desired_width(120.0));
+        });
+        if let Some(e) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+
+        let filter = self.filter.to_lowercase();
+        let shown: Vec<&Palette> = self.library.iter()
+            .filter(|p| p.name.to_lowercase().contains(&filter))
+            .collect();
+        let row_height = 20.0;
+        let mut picked = None;
+        egui::ScrollArea::vertical().auto_shrink([false, true]).show_rows(ui, row_height, shown.len(), |ui, rows| {
+            for p in &shown[rows] {
+                ui.horizontal(|ui| {
+                    let (rect, response) = ui.allocate_exact_size(vec2(160.0, row_height - 4.0), Sense::click());
+                    paint_palette(ui, rect, p);
+                    let response = response.on_hover_text("Use this palette");
+                    if response.clicked() || ui.selectable_label(p.name == palette.name, &p.name).clicked() {
+                        picked = Some((*p).clone());
+                    }
+                });
+            }
         });
+        if let Some(p) = picked {
+            self.push_undo(before.clone());
+            *palette = p;
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_dialog(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .add_filter("Palettes", &["ugr", "gradient", "map", "gpl"])
+            .add_filter("Apophysis gradients", &["ugr", "gradient"])
+            .add_filter("flam3 palette", &["map"])
+            .add_filter("GIMP palette", &["gpl"])
+            .pick_file();
+        let Some(path) = picked else {
+            return;
+        };
+        match load_palettes(&path) {
+            Ok(palettes) => {
+                self.library = palettes;
+                self.library_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+}
+
+/// The palette as a strip, left to right, as the kernel sees it: resampled to MAX_PALETTE_COLORS, then rotated
+fn paint_palette(ui: &Ui, rect: Rect, palette: &Palette) {
+    let painter = ui.painter_at(rect);
+    let colors = palette.resample(MAX_PALETTE_COLORS);
+    let width = rect.width() / colors.len().max(1) as f32;
+    for (i, c) in colors.iter().enumerate() {
+        let min = pos2(rect.min.x + i as f32 * width, rect.min.y);
+        let to8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+        painter.rect_filled(Rect::from_min_size(min, vec2(width + 0.5, rect.height())), 0.0,
+                            Color32::from_rgb(to8(c[0]), to8(c[1]), to8(c[2])));
     }
-}
\ No newline at end of file
+    painter.rect_stroke(rect, 0.0, Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color));
+}
+
+/// The three curves sampled at 256 evenly spaced points, as rgb bytes
+pub fn bake_hsv_curves(h: &[Pos2], s: &[Pos2], v: &[Pos2]) -> [u8; 256*3] {
+    let mut baked = [0; 256*3];
+    for (i, rgb) in baked.chunks_exact_mut(3).enumerate() {
+        let x = i as f32 / 255.0;
+        let c = hsv_to_rgb(eval_curve(h, x), eval_curve(s, x).clamp(0.0, 1.0), eval_curve(v, x).clamp(0.0, 1.0));
+        for (byte, channel) in rgb.iter_mut().zip(c) {
+            *byte = (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+    baked
+}
@@ -0,0 +1,17 @@
+#![warn(clippy::all, rust_2018_idioms)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(non_snake_case)]
+
+mod app;
+pub mod rendering;
+mod editors;
+
+pub mod model;
+
+pub use app::Display;
+
+mod viewport;
+pub mod util;
+pub mod alias_method;
+mod tests;
@@ -4,20 +4,8 @@
 #![allow(non_snake_case)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-mod app;
-mod rendering;
-mod editors;
-
-mod model;
-
-pub use app::Display;
-
-mod viewport;
-mod util;
-mod tests;
-
-
 use eframe::Renderer::Wgpu;
+use IFSRS::Display;
 
 // use re_memory::AccountingAllocator;
 
@@ -1,18 +1,31 @@
 use std::hash::{Hash, Hasher};
-use std::ops::Index;
+use std::ops::{Index, IndexMut};
+use anyhow::{bail, Result};
 use std::path::Iter;
 use egui_winit::winit::dpi::Pixel;
 use nalgebra::{Point3, Quaternion};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use crate::model::camera::Camera;
+use crate::model::ifsjson::IfsJsonExtras;
 use crate::model::iterator::Iterator;
+use crate::model::palette::Palette;
+use crate::model::response_curves::ResponseCurves;
+use crate::model::xaos::{Xaos, XaosEdge};
+use crate::rendering::graphics_engine::MAX_ITERATORS;
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Author {
+    pub name: String,
+    pub link: String,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct IFS {
     pub title: String,
+    pub authors: Vec<Author>,
     pub iterators: Vec<Iterator>,
-
+    pub xaos: Xaos, //weights between iterators, by id
     pub width : u32,
     pub height : u32,
 
@@ -21,6 +34,10 @@ pub struct IFS {
     pub gamma_thresh: f64, //strictly >= 0
     pub vibrancy: f64, //can be positive or negative
     pub background_color: [f32; 3],
+    pub fog_effect: f32,
+    pub palette: Palette,
+    #[serde(default)] //worlds from before there were curves load with them flat
+    pub response_curves: ResponseCurves,
     //camera settings struct
     pub camera: Camera,
     //render settings
@@ -28,12 +45,19 @@ pub struct IFS {
     pub fuse: u32, // usually 20, number of iterations to discard before plotting
     pub stopping_sl: f32, //also known as target iteration level
     #[serde(skip)]
+    pub use_stopping_sl: bool, //stop once the sampling level reaches stopping_sl
+    #[serde(skip)]
     pub pause_rendering: bool,
+    #[serde(skip)]
+    pub ifsjson_extras: IfsJsonExtras, //from an IFSRenderer world, for writing it back
 }
 
 impl Hash for IFS {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.iterators.hash(state);
+        for it in &self.iterators {
+            it.hash_values(state);
+        }
+        self.xaos.hash(state);
         self.width.hash(state);
         self.height.hash(state);
         self.camera.hash(state);
@@ -41,6 +65,8 @@ impl Hash for IFS {
             std::mem::transmute::<f32, u32>(self.entropy).hash(state);
         }
         self.fuse.hash(state);
+        self.fog_effect.to_bits().hash(state);
+        self.palette.hash(state);
     }
 }
 
@@ -50,13 +76,56 @@ impl IFS {
         self.hash(&mut s);
         s.finish()
     }
+
+    /// What get_hash covers, as a digest that's the same across builds and platforms, to tell saved accumulations apart.
+    /// It's over the world as it's saved, keys sorted, less what doesn't go into the histogram.
+    pub fn histogram_digest(&self) -> Result<u64> {
+        let toml::Value::Table(mut world) = toml::Value::try_from(self)? else {
+            bail!("The world didn't serialize to a table");
+        };
+        for key in ["title", "authors", "brightness", "gamma_inv", "gamma_thresh", "vibrancy", "background_color", "response_curves", "stopping_sl"] {
+            world.remove(key);
+        }
+        for it in world.get_mut("iterators").and_then(toml::Value::as_array_mut).into_iter().flatten() {
+            it.as_table_mut().and_then(|it| it.remove("name"));
+        }
+        world.get_mut("palette").and_then(toml::Value::as_table_mut).and_then(|palette| palette.remove("name"));
+        let digest = Sha256::digest(world.to_string());
+        Ok(u64::from_le_bytes(digest[..8].try_into()?))
+    }
+
+    /// Covers what get_hash leaves out because it doesn't change what goes in the histogram,
+    /// how it's shown and when to stop filling it, as far as it's saved with the world
+    pub fn settings_hash(&self) -> u64 {
+        let mut s = std::hash::DefaultHasher::new();
+        self.brightness.to_bits().hash(&mut s);
+        self.gamma_inv.to_bits().hash(&mut s);
+        self.gamma_thresh.to_bits().hash(&mut s);
+        self.vibrancy.to_bits().hash(&mut s);
+        self.background_color.map(f32::to_bits).hash(&mut s);
+        self.response_curves.hash(&mut s);
+        self.stopping_sl.to_bits().hash(&mut s);
+        s.finish()
+    }
+
+    /// settings_hash plus the session-only switches, which the engine has to hear about too
+    pub fn display_hash(&self, settings_hash: u64) -> u64 {
+        let mut s = std::hash::DefaultHasher::new();
+        settings_hash.hash(&mut s);
+        self.use_stopping_sl.hash(&mut s);
+        self.pause_rendering.hash(&mut s);
+        s.finish()
+    }
 }
 
 impl Default for IFS {
     fn default() -> Self {
+        let it = Iterator::default();
         Self {
             title: String::from("Untitled"),
-            iterators: vec!(Iterator::default()),
+            authors: vec![],
+            xaos: Xaos::from(vec![XaosEdge { from: it.id, to: it.id, weight: 1.0 }]),
+            iterators: vec!(it),
             width: 512,
             height: 512,
             brightness: 1.0,
@@ -64,37 +133,43 @@ impl Default for IFS {
             gamma_thresh: 0.0,
             vibrancy: 1.0,
             background_color: [0.0, 0.0, 0.0],
+            fog_effect: 0.0,
+            palette: Palette::default(),
+            response_curves: ResponseCurves::default(),
             camera: Camera::default(),
             entropy: 0.01,
             fuse: 20,
             stopping_sl: 15.0,
+            use_stopping_sl: false,
             pause_rendering: false,
+            ifsjson_extras: IfsJsonExtras::default(),
         }
     }
 }
 
-//TODO--indexing an IFS should return the iterator with an id that matches i
-/*
-impl Index<usize> for IFS{
+/// `ifs[(from, to)]`, the weight between two iterators
+impl Index<(&Iterator, &Iterator)> for IFS {
+    type Output = f64;
+    fn index(&self, (from, to): (&Iterator, &Iterator)) -> &Self::Output {
+        &self.xaos[(from.id, to.id)]
+    }
+}
+
+/// `ifs[id]`, the iterator with that id. Panics if there isn't one, use `get` if that's possible.
+impl Index<i32> for IFS {
     type Output = Iterator;
-    fn index<'a>(&'a self, i: usize) -> &Iterator{
-        return Iterator::default()
+    fn index(&self, id: i32) -> &Iterator {
+        self.get(id).unwrap_or_else(|| panic!("No iterator with id {id}"))
     }
-}*/
+}
 
-impl IFS{
-    fn add_iterator(&mut self, new_iterator: Iterator, connect:bool){
-        self.iterators.push(new_iterator);
-        //let mut ni = &self.iterators.last().expect("JUST PUSHED");
-        //let weight: f64 = if connect {1.0} else  {0.0};
-        if connect{
-            for it in &mut self.iterators {
-                //new_iterator.weight_to[it] = 1.0;
-                //it.weight_to[new_iterator] = 1.0;
-            }
-        }
+impl IndexMut<i32> for IFS {
+    fn index_mut(&mut self, id: i32) -> &mut Iterator {
+        self.get_mut(id).unwrap_or_else(|| panic!("No iterator with id {id}"))
     }
+}
 
+impl IFS{
     pub fn cube_example() -> Self {
         // let mut ah_tchip = vec![];
         //
@@ -112,9 +187,12 @@ impl IFS{
         //     weight_to.insert(next_in, 1.0);
         // }
 
+        let it = Iterator::default();
         Self{
             title: String::from("CUBE"),
-            iterators: vec!(Iterator::default()),
+            authors: vec![],
+            xaos: Xaos::from(vec![XaosEdge { from: it.id, to: it.id, weight: 1.0 }]),
+            iterators: vec!(it),
             width: 512,
             height: 512,
             brightness: 1.0,
@@ -122,6 +200,9 @@ impl IFS{
             gamma_thresh: 0.0,
             vibrancy: 1.0,
             background_color: [0.0, 0.0, 0.0],
+            fog_effect: 0.0,
+            palette: Palette::default(),
+            response_curves: ResponseCurves::default(),
             camera: Camera {
                 position: Point3::new(1.5297344,  -2.8017617, -4.790808),
                 orientation: Quaternion::new(0.71461225, -0.33977485, -0.060941823, 0.0),
@@ -134,41 +215,113 @@ impl IFS{
             entropy: 0.01,
             fuse: 20,
             stopping_sl: 15.0,
+            use_stopping_sl: false,
             pause_rendering: false,
+            ifsjson_extras: IfsJsonExtras::default(),
+        }
+    }
+
+    pub fn iterator_index(&self, id: i32) -> Option<usize> {
+        self.iterators.iter().position(|it| it.id == id)
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Iterator> {
+        self.iterators.iter().find(|it| it.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: i32) -> Option<&mut Iterator> {
+        self.iterators.iter_mut().find(|it| it.id == id)
+    }
+
+    /// For worlds read from files: ids have to be unique, and new ones must not collide with them
+    pub fn claim_ids(&self) -> Result<()> {
+        for (i, it) in self.iterators.iter().enumerate() {
+            if self.iterators[..i].iter().any(|other| other.id == it.id) {
+                bail!("Two iterators share the id {}", it.id);
+            }
+            Iterator::reserve_id(it.id);
         }
+        Ok(())
+    }
+
+    /// The first id from `next_id` that no iterator here has. Once it runs out, the lowest positive one
+    /// that's free, which there always is, there can't be more than MAX_ITERATORS taken.
+    pub fn unused_id(&self, mut next_id: impl FnMut() -> Option<i32>) -> i32 {
+        while let Some(id) = next_id() {
+            if self.get(id).is_none() {
+                return id;
+            }
+        }
+        (1..).find(|id| self.get(*id).is_none()).unwrap_or_default()
+    }
+
+    /// The kernel has room for MAX_ITERATORS
+    pub fn is_full(&self) -> bool {
+        self.iterators.len() >= MAX_ITERATORS
     }
-    fn dup_iterator(&mut self, mut original: Iterator, split_weights: bool) -> &Iterator{
-        self.add_iterator(original.clone(), false);
-        let mut dup = self.iterators.last().expect("JUST PUSHED");
-        //for it in &mut self.iterators{
-            //if w = it.weight_to[original] ...exists?
-            //it.weight_to[dup] = w
-       // }
-        //for it in &mut dup.weight_to{
-            //if w = it.weight_to[original] ...exists?
-            //it.weight_to[dup] = w
-        //}
-        if split_weights{
-            original.base_weight /= 2.0;
-            //dup.base_weight = original.base_weight;
-            //dup.weight_to[original] = 0.0;
-            //original.weight_to[dup] = 0.0;
+
+    /// Appends an iterator and returns its id, which is reassigned if another iterator already has it.
+    /// A connected iterator gets a weight of 1 to and from every iterator, itself included.
+    /// A disconnected one gets none at all. Returns None if the world is full.
+    pub fn add_iterator(&mut self, mut new_iterator: Iterator, connect: bool) -> Option<i32> {
+        if self.is_full() {
+            return None;
         }
-        return dup
+        if self.get(new_iterator.id).is_some() {
+            new_iterator.id = self.unused_id(Iterator::next_id);
+        }
+        let new_id = new_iterator.id;
+        self.iterators.push(new_iterator);
+        if connect {
+            for it in &self.iterators {
+                self.xaos.set(new_id, it.id, 1.0);
+                self.xaos.set(it.id, new_id, 1.0);
+            }
+        }
+        Some(new_id)
     }
 
-    fn del_iterator(&mut self, it: Iterator){
-        if !self.iterators.contains(&it){
-            panic!("Unable to remove iterator with id {} from IFS", it.id);
+    /// Adds a copy of an iterator under a new id, with the same weights coming in and going out.
+    /// Splitting halves the base weight of both, so the pair gets picked as often as the original was.
+    /// Returns the copy's id, or None if there's no iterator with that id or the world is full.
+    pub fn dup_iterator(&mut self, id: i32, split_weights: bool) -> Option<i32> {
+        let i = self.iterator_index(id)?;
+        if self.is_full() {
+            return None;
+        }
+        if split_weights {
+            self.iterators[i].base_weight /= 2.0;
+        }
+        let dup_id = self.add_iterator(self.iterators[i].clone(), false)?;
+
+        let incoming: Vec<(i32, f64)> = self.xaos.incoming(id).collect();
+        let outgoing: Vec<(i32, f64)> = self.xaos.outgoing(id).collect();
+        for (from, w) in incoming {
+            self.xaos.set(from, dup_id, w);
         }
-        //remove related anim channels
-
-        //remove weights
-        //for it2 in self.iterators{
-            //it.weight_to.remove(it2);
-            //it2.weight_to.remove(it);
-        //}
-        //self.iterators.remove(indexof(it))
+        for (to, w) in outgoing {
+            self.xaos.set(dup_id, to, w);
+        }
+        //a loop on the original becomes a loop on the copy, on top of the edges between them
+        self.xaos.set(dup_id, dup_id, self.xaos.get(id, id));
+        Some(dup_id)
+    }
+
+    /// Removes an iterator along with every weight to or from it.
+    /// Automation targets live in the AutomationEditor, which the caller has to tell separately.
+    pub fn del_iterator(&mut self, id: i32) -> Option<Iterator> {
+        let i = self.iterator_index(id)?;
+        self.xaos.remove_iterator(id);
+        Some(self.iterators.remove(i))
+    }
+
+    /// Moves an iterator to `new_index` (clamped to the end), shifting the ones in between.
+    /// Weights are by id, so they don't change.
+    pub fn reorder_iterator(&mut self, id: i32, new_index: usize) -> bool {
+        let Some(i) = self.iterator_index(id) else { return false };
+        let it = self.iterators.remove(i);
+        self.iterators.insert(new_index.min(self.iterators.len()), it);
+        true
     }
 
     //takes enumerable of transforms, for each iterator in self.iterators,,, set the transform to the next one in the list? idk
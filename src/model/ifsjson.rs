@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use nalgebra::{Point3, Quaternion};
+use serde::{Deserialize, Serialize};
+use crate::model::camera::Camera;
+use crate::model::ifs::{Author, IFS};
+use crate::model::iterator::Iterator;
+use crate::model::palette::Palette;
+use crate::model::response_curves::ResponseCurves;
+use crate::model::transform::Transform;
+use crate::model::xaos::Xaos;
+use crate::rendering::graphics_engine::MAX_ITERATORS;
+
+// Mirrors the schema IFSRenderer (the C# original) reads and writes as .ifsjson files.
+// Only the subset we have a home for is mapped onto the model; the rest is parsed so that
+// malformed files are still rejected. The weight graph layout and the dopesheet are kept in IfsJsonExtras
+// and written back as they were, everything else is dropped and filled with IFSRenderer's defaults on export.
+
+/// What an IFSRenderer world has that nothing here edits yet, kept so exporting it again doesn't lose it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IfsJsonExtras {
+    pub node_positions: HashMap<i32, [f64; 2]>, //weight graph layout, by iterator id
+    pub dopesheet: Option<serde_json::Value>, //animation
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IfsJson {
+    title: String,
+    #[serde(default)]
+    authors: Vec<AuthorJson>,
+    iterators: Vec<IteratorJson>,
+    entropy: f32,
+    warmup: u32,
+    brightness: f64,
+    gamma: f64,
+    gamma_threshold: f64,
+    vibrancy: f64,
+    #[serde(default)]
+    fog_effect: f32,
+    camera: CameraJson,
+    background_color: String,
+    image_resolution: String,
+    palette: Option<PaletteJson>,
+    dopesheet: Option<serde_json::Value>, //no animation model yet, kept in IfsJsonExtras
+    target_iteration_level: f32,
+    #[serde(rename = "xaos", default)]
+    xaos: Vec<Vec<f64>>,
+    #[serde(default)]
+    nodes: Vec<Vec2Json>, //weight graph node positions in iterator order, kept in IfsJsonExtras
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AuthorJson {
+    name: String,
+    link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IteratorJson {
+    id: i32,
+    name: Option<String>,
+    transform: TransformRefJson,
+    #[serde(default)]
+    real_params: BTreeMap<String, f32>,
+    #[serde(default)]
+    vec3_params: BTreeMap<String, Vec3Json>,
+    base_weight: f32,
+    color_speed: f32,
+    color_index: f32,
+    start_weight: f32,
+    opacity: f32,
+    mix: f32,
+    add: f32,
+    #[serde(default)]
+    shading_mode: i32,
+}
+
+/// C# serializes the (name, version) tuple as Item1/Item2
+#[derive(Serialize, Deserialize, Debug)]
+struct TransformRefJson {
+    #[serde(rename = "Item1")]
+    name: String,
+    #[serde(rename = "Item2")]
+    version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CameraJson {
+    orientation: QuaternionJson,
+    position: Vec3Json,
+    field_of_view: f64,
+    aperture: f64,
+    focus_distance: f64,
+    depth_of_field: f64,
+    #[serde(default)]
+    projection: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct PaletteJson {
+    name: String,
+    #[serde(default)]
+    rotation: i32,
+    colors: Vec<Vec4Json>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+struct QuaternionJson {
+    x: f64,
+    y: f64,
+    z: f64,
+    w: f64,
+    #[serde(default)]
+    is_identity: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+struct Vec2Json {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+struct Vec3Json {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "PascalCase")]
+struct Vec4Json {
+    x: f32,
+    y: f32,
+    z: f32,
+    w: f32,
+}
+
+impl IFS {
+    /// # From IFSJson
+    /// Reads a world saved by IFSRenderer.
+    /// Transforms are referenced by name and version in these files, so `find_transform` is asked
+    /// to resolve each one. If any can't be found, the error lists all of them.
+    pub fn from_ifsjson<F>(src: &str, find_transform: F) -> Result<IFS>
+    where
+        F: Fn(&str, &str) -> Option<Transform>,
+    {
+        //files written by .NET usually start with a BOM, which serde_json refuses
+        let src = src.trim_start_matches('\u{feff}');
+        let world: IfsJson = serde_json::from_str(src).context("Not a valid IFSRenderer world")?;
+
+        let mut missing = vec![];
+        let mut iterators = vec![];
+        let node_positions = world.iterators.iter().zip(&world.nodes)
+            .map(|(it, node)| (it.id, [node.x, node.y]))
+            .collect();
+        for it in world.iterators {
+            let Some(transform) = find_transform(&it.transform.name, &it.transform.version) else {
+                missing.push(format!("{} {}", it.transform.name, it.transform.version));
+                continue;
+            };
+            // start from the transform's defaults so params the file doesn't mention still exist
+            let mut real_params = transform.real_params.clone();
+            real_params.extend(it.real_params);
+            let mut vec3_params = transform.vec3_params.clone();
+            vec3_params.extend(it.vec3_params.into_iter().map(|(k, v)| (k, [v.x as f32, v.y as f32, v.z as f32])));
+
+            iterators.push(Iterator {
+                id: it.id,
+                name: it.name.unwrap_or_else(|| transform.name.clone()),
+                transform,
+                real_params,
+                vec3_params,
+                base_weight: it.base_weight,
+                color_speed: it.color_speed,
+                color_index: it.color_index,
+                start_weight: it.start_weight,
+                opacity: it.opacity,
+                mix: it.mix,
+                add: it.add,
+                shading_mode: it.shading_mode,
+            });
+        }
+        if !missing.is_empty() {
+            bail!("Missing transforms: {}", missing.join(", "));
+        }
+
+        let n = iterators.len();
+        let mut xaos = Xaos::default();
+        if world.xaos.is_empty() {
+            //older files may omit the matrix, which IFSRenderer treats as fully connected
+            for it in &iterators {
+                for to in &iterators {
+                    xaos.set(it.id, to.id, 1.0);
+                }
+            }
+        } else {
+            if world.xaos.len() != n || world.xaos.iter().any(|row| row.len() != n) {
+                bail!("xaos matrix should be {n}x{n} to match the iterators");
+            }
+            for (it, row) in iterators.iter().zip(&world.xaos) {
+                for (to, w) in iterators.iter().zip(row) {
+                    xaos.set(it.id, to.id, *w);
+                }
+            }
+        }
+
+        let (width, height) = parse_resolution(&world.image_resolution)?;
+        let background_color = parse_color(&world.background_color).unwrap_or_else(|| {
+            log::warn!("Unknown background color {:?}, using black", world.background_color);
+            [0.0, 0.0, 0.0]
+        });
+
+        let q = world.camera.orientation;
+        let p = world.camera.position;
+        let mut camera = Camera {
+            position: Point3::new(p.x, p.y, p.z),
+            orientation: Quaternion::new(q.w, q.x, q.y, q.z),
+            fov: world.camera.field_of_view,
+            aperture: world.camera.aperture,
+            focus_distance: world.camera.focus_distance,
+            dof: world.camera.depth_of_field,
+            ..Camera::default()
+        };
+        camera.update_direction_vectors();
+
+        let palette = match world.palette {
+            Some(pal) => Palette {
+                name: pal.name,
+                rotation: pal.rotation,
+                colors: pal.colors.iter().map(|c| [c.x, c.y, c.z, c.w]).collect(),
+            },
+            None => Palette::default(),
+        };
+
+        let ifs = IFS {
+            title: world.title,
+            authors: world.authors.into_iter()
+                .map(|a| Author { name: a.name, link: a.link.unwrap_or_default() })
+                .collect(),
+            iterators,
+            xaos,
+            width,
+            height,
+            brightness: world.brightness,
+            gamma_inv: if world.gamma > 0.0 { 1.0 / world.gamma } else { 1.0 },
+            gamma_thresh: world.gamma_threshold,
+            vibrancy: world.vibrancy,
+            background_color,
+            fog_effect: world.fog_effect,
+            palette,
+            response_curves: ResponseCurves::default(),
+            camera,
+            entropy: world.entropy,
+            fuse: world.warmup,
+            stopping_sl: world.target_iteration_level,
+            use_stopping_sl: false,
+            pause_rendering: false,
+            ifsjson_extras: IfsJsonExtras { node_positions, dopesheet: world.dopesheet },
+        };
+        if ifs.iterators.len() > MAX_ITERATORS {
+            bail!("The world has {} iterators, IFSRS can render up to {MAX_ITERATORS}", ifs.iterators.len());
+        }
+        ifs.claim_ids()?;
+        Ok(ifs)
+    }
+
+    pub fn load_ifsjson<F>(path: &Path, find_transform: F) -> Result<IFS>
+    where
+        F: Fn(&str, &str) -> Option<Transform>,
+    {
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_ifsjson(&src, find_transform)
+            .with_context(|| format!("Couldn't load {}", path.display()))
+    }
+
+    /// # To IFSJson
+    /// Writes the world in the format IFSRenderer reads.
+    /// Weights are written as the dense `xaos` matrix, in iterator order.
+    pub fn to_ifsjson(&self) -> Result<String> {
+        let iterators = self.iterators.iter().map(|it| IteratorJson {
+            id: it.id,
+            name: Some(it.name.clone()),
+            transform: TransformRefJson {
+                name: it.transform.name.clone(),
+                version: it.transform.version.clone(),
+            },
+            real_params: it.real_params.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            vec3_params: it.vec3_params.iter()
+                .map(|(k, v)| (k.clone(), Vec3Json { x: v[0] as f64, y: v[1] as f64, z: v[2] as f64 }))
+                .collect(),
+            base_weight: it.base_weight,
+            color_speed: it.color_speed,
+            color_index: it.color_index,
+            start_weight: it.start_weight,
+            opacity: it.opacity,
+            mix: it.mix,
+            add: it.add,
+            shading_mode: it.shading_mode,
+        }).collect();
+
+        let xaos = self.iterators.iter()
+            .map(|it| self.iterators.iter().map(|to| self[(it, to)]).collect())
+            .collect();
+
+        //IFSRenderer's weight graph needs somewhere to put the nodes, imported ones stay put and the rest go in a circle
+        let n = self.iterators.len() as f64;
+        let nodes = self.iterators.iter().enumerate().map(|(i, it)| {
+            if let Some([x, y]) = self.ifsjson_extras.node_positions.get(&it.id) {
+                return Vec2Json { x: *x, y: *y };
+            }
+            let a = std::f64::consts::TAU * i as f64 / n;
+            Vec2Json { x: 400.0 + 200.0 * a.cos(), y: 300.0 + 200.0 * a.sin() }
+        }).collect();
+
+        let q = self.camera.orientation;
+        let p = self.camera.position;
+        let world = IfsJson {
+            title: self.title.clone(),
+            authors: self.authors.iter()
+                .map(|a| AuthorJson { name: a.name.clone(), link: Some(a.link.clone()) })
+                .collect(),
+            iterators,
+            entropy: self.entropy,
+            warmup: self.fuse,
+            brightness: self.brightness,
+            gamma: if self.gamma_inv > 0.0 { 1.0 / self.gamma_inv } else { 1.0 },
+            gamma_threshold: self.gamma_thresh,
+            vibrancy: self.vibrancy,
+            fog_effect: self.fog_effect,
+            camera: CameraJson {
+                orientation: QuaternionJson {
+                    x: q.i,
+                    y: q.j,
+                    z: q.k,
+                    w: q.w,
+                    is_identity: q == Quaternion::identity(),
+                },
+                position: Vec3Json { x: p.x, y: p.y, z: p.z },
+                field_of_view: self.camera.fov,
+                aperture: self.camera.aperture,
+                focus_distance: self.camera.focus_distance,
+                depth_of_field: self.camera.dof,
+                projection: 0,
+            },
+            background_color: format_color(self.background_color),
+            image_resolution: format!("{}, {}", self.width, self.height),
+            palette: Some(PaletteJson {
+                name: self.palette.name.clone(),
+                rotation: self.palette.rotation,
+                colors: self.palette.colors.iter()
+                    .map(|c| Vec4Json { x: c[0], y: c[1], z: c[2], w: c[3] })
+                    .collect(),
+            }),
+            dopesheet: Some(self.ifsjson_extras.dopesheet.clone().unwrap_or_else(|| serde_json::json!({
+                "Channels": {},
+                "Length": "00:00:10",
+                "Fps": 30
+            }))),
+            target_iteration_level: self.stopping_sl,
+            xaos,
+            nodes,
+        };
+        Ok(serde_json::to_string_pretty(&world)?)
+    }
+
+    pub fn save_ifsjson(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_ifsjson()?)
+            .with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+/// .NET writes sizes as "width, height"
+fn parse_resolution(s: &str) -> Result<(u32, u32)> {
+    let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+    if let [w, h] = parts[..] {
+        if let (Ok(w), Ok(h)) = (w.parse(), h.parse()) {
+            return Ok((w, h));
+        }
+    }
+    Err(anyhow!("ImageResolution {s:?} should look like \"width, height\""))
+}
+
+const NAMED_COLORS: [(&str, [u8; 3]); 8] = [
+    ("Black", [0, 0, 0]),
+    ("White", [255, 255, 255]),
+    ("Red", [255, 0, 0]),
+    ("Lime", [0, 255, 0]),
+    ("Blue", [0, 0, 255]),
+    ("Gray", [128, 128, 128]),
+    ("DimGray", [105, 105, 105]),
+    ("DarkGray", [169, 169, 169]),
+];
+
+/// System.Drawing.Color comes out as a known name, "r, g, b", "a, r, g, b" or "#rrggbb"
+fn parse_color(s: &str) -> Option<[f32; 3]> {
+    let s = s.trim();
+    let rgb: [u8; 3] = if let Some((_, c)) = NAMED_COLORS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+        *c
+    } else if let Some(hex) = s.strip_prefix('#') {
+        let v = u32::from_str_radix(hex, 16).ok()?;
+        match hex.len() {
+            6 | 8 => [(v >> 16) as u8, (v >> 8) as u8, v as u8],
+            _ => return None,
+        }
+    } else {
+        let parts: Vec<u8> = s.split(',').map(|p| p.trim().parse()).collect::<Result<_, _>>().ok()?;
+        match parts[..] {
+            [r, g, b] | [_, r, g, b] => [r, g, b],
+            _ => return None,
+        }
+    };
+    Some(rgb.map(|c| c as f32 / 255.0))
+}
+
+fn format_color(c: [f32; 3]) -> String {
+    let rgb = c.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8);
+    match NAMED_COLORS.iter().find(|(_, named)| *named == rgb) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{}, {}, {}", rgb[0], rgb[1], rgb[2]),
+    }
+}
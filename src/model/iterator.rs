@@ -1,12 +1,36 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::ops::{Deref, Index, IndexMut};
-use rand::random;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicI64, Ordering};
 use serde::{Deserialize, Serialize};
 use crate::model::transform::Transform;
 use crate::rendering::gpu_structs::IteratorStruct;
 
 
+/// Ids handed out so far, see next_id
+static IDS: IdAllocator = IdAllocator::new(1);
+
+/// Hands out ids in increasing order until they pass i32::MAX. Iterator::next_id uses one for the whole process.
+pub struct IdAllocator {
+    next: AtomicI64, //wider than the ids, so running out can't wrap around to ones already handed out
+}
+
+impl IdAllocator {
+    pub const fn new(first: i32) -> Self {
+        Self { next: AtomicI64::new(first as i64) }
+    }
+
+    /// A fresh id, unlike any handed out or reserved before, or None once they're used up
+    pub fn next_id(&self) -> Option<i32> {
+        i32::try_from(self.next.fetch_add(1, Ordering::Relaxed)).ok()
+    }
+
+    /// Keeps next_id from handing out `id` or anything below it
+    pub fn reserve_id(&self, id: i32) {
+        self.next.fetch_max(id as i64 + 1, Ordering::Relaxed);
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Iterator {
     pub id: i32,
@@ -21,14 +45,13 @@ pub struct Iterator {
     pub opacity: f32,
     pub mix: f32,
     pub add: f32,
-    //pub shading_mode: ShadingMode
-    pub weight_to: HashMap<Iterator, f64>,
+    pub shading_mode: i32, //0: default, 1: delta_p
 }
 
 impl Default for Iterator {
     fn default() -> Self {
         Self{
-            id: random(),
+            id: Iterator::next_id().unwrap_or_default(), //add_iterator replaces it if it's taken
             name: String::from("cubetest"),
             transform: Transform::cube(),
             real_params: HashMap::new(),
@@ -40,8 +63,7 @@ impl Default for Iterator {
             opacity: 1.0,
             mix: 1.0,
             add: 0.0,
-            //shading_mode: ShadingMode
-            weight_to: HashMap::new(),
+            shading_mode: 0,
         }
     }
 }
@@ -56,20 +78,7 @@ impl Eq for Iterator {} // Required for the Hash trait
 
 impl Hash for Iterator {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.id;
-    }
-}
-
-impl Index<&Iterator> for Iterator {
-    type Output = f64;
-    fn index(&self, index: &Iterator) -> &Self::Output {
-        self.weight_to.get(index).unwrap_or(&0.0) // Use 0.0 as default if not found
-    }
-}
-
-impl IndexMut<&Iterator> for Iterator {
-    fn index_mut(&mut self, index: &Iterator) -> &mut Self::Output {
-        self.weight_to.entry(index.clone()).or_insert(0.0)
+        self.id.hash(state); //see hash_values for everything else
     }
 }
 
@@ -81,9 +90,61 @@ impl IndexMut<&Iterator> for Iterator {
 // }
 
 impl Iterator{
-    fn set_transform(&mut self, tf: Transform) {
+    /// A fresh id, unlike any handed out or reserved before in this process, or None once a world file has
+    /// reserved the last of them. IFS::add_iterator finds one the world doesn't use then.
+    pub fn next_id() -> Option<i32> {
+        IDS.next_id()
+    }
+
+    /// Keeps next_id from handing out an id that came from somewhere else, like a world file
+    pub fn reserve_id(id: i32) {
+        IDS.reserve_id(id);
+    }
+
+    /// Hashes everything the renderer reads. `Hash` itself only covers the id, to agree with Eq
+    pub fn hash_values<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.transform.name.hash(state);
+        self.transform.version.hash(state);
+        self.transform.source_code.hash(state);
+        let mut real: Vec<_> = self.real_params.iter().collect();
+        real.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, val) in real {
+            name.hash(state);
+            val.to_bits().hash(state);
+        }
+        let mut vec3: Vec<_> = self.vec3_params.iter().collect();
+        vec3.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, val) in vec3 {
+            name.hash(state);
+            val.map(f32::to_bits).hash(state);
+        }
+        for v in [self.base_weight, self.color_speed, self.color_index, self.start_weight, self.opacity, self.mix, self.add] {
+            v.to_bits().hash(state);
+        }
+        self.shading_mode.hash(state);
+    }
+
+    pub fn from_transform(tf: Transform) -> Self {
+        let mut it = Self {
+            name: tf.name.clone(),
+            ..Self::default()
+        };
+        it.set_transform(tf);
+        it
+    }
+
+    /// Params take the new transform's defaults, except those the old transform shared by name,
+    /// which keep their values
+    pub fn set_transform(&mut self, tf: Transform) {
+        let old_real = std::mem::take(&mut self.real_params);
+        let old_vec3 = std::mem::take(&mut self.vec3_params);
+        self.real_params = tf.real_params.iter()
+            .map(|(k, v)| (k.clone(), *old_real.get(k).unwrap_or(v)))
+            .collect();
+        self.vec3_params = tf.vec3_params.iter()
+            .map(|(k, v)| (k.clone(), *old_vec3.get(k).unwrap_or(v)))
+            .collect();
         self.transform = tf;
-        self.real_params = self.transform.real_params.clone();
-        self.vec3_params = self.transform.vec3_params.clone(); //??????? this seems gone
     }
 }
\ No newline at end of file
@@ -2,3 +2,11 @@ pub mod ifs;
 pub mod iterator;
 pub mod transform;
 pub mod camera;
+pub mod palette;
+pub mod palette_import;
+pub mod palette_generation;
+pub mod response_curves;
+pub mod ifsjson;
+pub mod world_file;
+pub mod transform_library;
+pub mod xaos;
@@ -0,0 +1,59 @@
+use std::hash::{Hash, Hasher};
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub rotation: i32, //shifts the colors along the palette by this many of the resampled entries, wrapping around
+    pub colors: Vec<[f32; 4]>, //rgba, evenly spaced along [0,1]
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            name: String::from("Default Palette"),
+            rotation: 0,
+            colors: vec![[1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 1.0]],
+        }
+    }
+}
+
+/// What the render sees, the name is left out
+impl Hash for Palette {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rotation.hash(state);
+        for c in &self.colors {
+            c.map(f32::to_bits).hash(state);
+        }
+    }
+}
+
+impl Palette {
+    /// Interpolated color at `pos` in [0,1], before rotation
+    pub fn sample(&self, pos: f32) -> [f32; 4] {
+        match self.colors.len() {
+            0 => [1.0; 4],
+            1 => self.colors[0],
+            n => {
+                let x = pos.clamp(0.0, 1.0) * (n - 1) as f32;
+                let i = (x.floor() as usize).min(n - 2);
+                let t = x - i as f32;
+                let (c1, c2) = (self.colors[i], self.colors[i + 1]);
+                [0, 1, 2, 3].map(|k| c1[k] + (c2[k] - c1[k]) * t)
+            }
+        }
+    }
+
+    /// `n` evenly spaced colors with the rotation applied, as the kernel reads them
+    pub fn resample(&self, n: usize) -> Vec<[f32; 4]> {
+        if n == 0 {
+            return vec![];
+        }
+        let mut colors: Vec<[f32; 4]> = (0..n)
+            .map(|i| self.sample(if n == 1 { 0.0 } else { i as f32 / (n - 1) as f32 }))
+            .collect();
+        colors.rotate_right(self.rotation.rem_euclid(n as i32) as usize);
+        colors
+    }
+}
@@ -0,0 +1,197 @@
+//! Palettes made from scratch, from other palettes, or from images. Anything random takes a seed,
+//! and the same seed always makes the same palette.
+
+use std::f32::consts::TAU;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use strum_macros::EnumIter;
+use crate::model::palette::Palette;
+use crate::rendering::graphics_engine::MAX_PALETTE_COLORS;
+use crate::util::math_extensions::{hsv_to_rgb, srgb_to_linear};
+
+/// Most pixels k-means looks at, images bigger than this are strided through
+const KMEANS_MAX_SAMPLES: usize = 20_000;
+const KMEANS_MAX_ROUNDS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum Scheme {
+    Analogous, //neighbouring hues
+    Triadic,   //three hues a third of the way around from each other
+    Cosine,    //a + b*cos(2pi(c*t + d)) per channel
+}
+
+impl Scheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Analogous => "Analogous",
+            Scheme::Triadic => "Triadic",
+            Scheme::Cosine => "Cosine gradient",
+        }
+    }
+}
+
+fn rgba([r, g, b]: [f32; 3]) -> [f32; 4] {
+    [r, g, b, 1.0]
+}
+
+/// Evenly spaced stops stretched over the whole palette
+fn from_stops(name: String, stops: Vec<[f32; 4]>) -> Palette {
+    let palette = Palette { name, rotation: 0, colors: stops };
+    Palette { colors: palette.resample(MAX_PALETTE_COLORS), ..palette }
+}
+
+/// A random palette in `scheme`, the same one every time for the same seed
+pub fn generate(scheme: Scheme, seed: u64) -> Palette {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let name = format!("{} {seed}", scheme.name());
+    match scheme {
+        Scheme::Analogous => {
+            let hue = rng.random::<f32>();
+            let spread = rng.random_range(0.04..0.12);
+            let stops = (0..5)
+                .map(|i| {
+                    let h = hue + spread * (i as f32 - 2.0) + rng.random_range(-0.02..0.02);
+                    rgba(hsv_to_rgb(h, rng.random_range(0.4..0.9), rng.random_range(0.35..1.0)))
+                })
+                .collect();
+            from_stops(name, stops)
+        }
+        Scheme::Triadic => {
+            let hue = rng.random::<f32>();
+            let mut stops = vec![];
+            for i in 0..3 {
+                let h = hue + i as f32 / 3.0;
+                let s = rng.random_range(0.5..1.0);
+                stops.push(rgba(hsv_to_rgb(h, s, rng.random_range(0.15..0.45))));
+                stops.push(rgba(hsv_to_rgb(h, s, rng.random_range(0.7..1.0))));
+            }
+            from_stops(name, stops)
+        }
+        Scheme::Cosine => {
+            let a: [f32; 3] = std::array::from_fn(|_| rng.random_range(0.3..0.7));
+            let b: [f32; 3] = std::array::from_fn(|_| rng.random_range(0.2..0.5));
+            let c: [f32; 3] = std::array::from_fn(|_| rng.random_range(0.5..1.5));
+            let d: [f32; 3] = std::array::from_fn(|_| rng.random::<f32>());
+            let colors = (0..MAX_PALETTE_COLORS)
+                .map(|i| {
+                    let t = i as f32 / (MAX_PALETTE_COLORS - 1) as f32;
+                    rgba([0, 1, 2].map(|k| (a[k] + b[k] * (TAU * (c[k] * t + d[k])).cos()).clamp(0.0, 1.0)))
+                })
+                .collect();
+            Palette { name, rotation: 0, colors }
+        }
+    }
+}
+
+/// Each color averaged with the `radius` colors either side of it, the ends don't wrap
+pub fn smooth(palette: &Palette, radius: usize) -> Palette {
+    let n = palette.colors.len();
+    let colors = (0..n)
+        .map(|i| {
+            let window = &palette.colors[i.saturating_sub(radius)..(i + radius + 1).min(n)];
+            let mut sum = [0.0; 4];
+            for c in window {
+                for k in 0..4 {
+                    sum[k] += c[k];
+                }
+            }
+            sum.map(|s| s / window.len() as f32)
+        })
+        .collect();
+    Palette { colors, ..palette.clone() }
+}
+
+/// CIE L*, how light an sRGB color looks, from 0 to 100
+pub fn lightness(c: &[f32]) -> f32 {
+    let y = 0.2126 * srgb_to_linear(c[0]) + 0.7152 * srgb_to_linear(c[1]) + 0.0722 * srgb_to_linear(c[2]);
+    if y > 216.0 / 24389.0 {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        y * 24389.0 / 27.0
+    }
+}
+
+/// The same colors, darkest first
+pub fn sort_by_lightness(palette: &Palette) -> Palette {
+    let mut colors = palette.colors.clone();
+    colors.sort_by(|a, b| lightness(a).total_cmp(&lightness(b)));
+    Palette { colors, ..palette.clone() }
+}
+
+fn distance2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|k| (a[k] - b[k]).powi(2)).sum()
+}
+
+/// `k` colors that the samples cluster around, seeded with k-means++
+pub fn kmeans(samples: &[[f32; 3]], k: usize, seed: u64) -> Vec<[f32; 3]> {
+    if samples.is_empty() || k == 0 {
+        return vec![];
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut centers = vec![samples[rng.random_range(0..samples.len())]];
+    let mut nearest: Vec<f32> = samples.iter().map(|s| distance2(s, &centers[0])).collect();
+    while centers.len() < k {
+        let total: f32 = nearest.iter().sum();
+        if total <= 0.0 {
+            break; //fewer distinct colors than clusters
+        }
+        let mut pick = rng.random::<f32>() * total;
+        let i = nearest.iter().position(|d| { pick -= d; pick <= 0.0 }).unwrap_or(samples.len() - 1);
+        centers.push(samples[i]);
+        for (d, s) in nearest.iter_mut().zip(samples) {
+            *d = d.min(distance2(s, &samples[i]));
+        }
+    }
+
+    let mut assignment = vec![usize::MAX; samples.len()];
+    for _ in 0..KMEANS_MAX_ROUNDS {
+        let mut moved = false;
+        for (a, s) in assignment.iter_mut().zip(samples) {
+            let closest = (0..centers.len())
+                .min_by(|&i, &j| distance2(s, &centers[i]).total_cmp(&distance2(s, &centers[j])))
+                .unwrap_or(0);
+            moved |= *a != closest;
+            *a = closest;
+        }
+        if !moved {
+            break;
+        }
+        let mut sums = vec![([0.0f32; 3], 0usize); centers.len()];
+        for (a, s) in assignment.iter().zip(samples) {
+            let (sum, count) = &mut sums[*a];
+            for (total, c) in sum.iter_mut().zip(s) {
+                *total += c;
+            }
+            *count += 1;
+        }
+        for (center, (sum, count)) in centers.iter_mut().zip(sums) {
+            if count > 0 {
+                *center = sum.map(|s| s / count as f32);
+            }
+        }
+    }
+    centers
+}
+
+/// A gradient through the `k` main colors of the image, darkest to lightest.
+/// Transparent pixels are left out.
+pub fn from_image(path: &Path, k: usize, seed: u64) -> Result<Palette> {
+    let image = image::open(path)
+        .with_context(|| format!("Couldn't load {}", path.display()))?
+        .to_rgba8();
+    let opaque: Vec<[f32; 3]> = image.pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| [p[0], p[1], p[2]].map(|c| c as f32 / 255.0))
+        .collect();
+    if opaque.is_empty() {
+        bail!("{} has no opaque pixels", path.display());
+    }
+    let stride = opaque.len().div_ceil(KMEANS_MAX_SAMPLES);
+    let samples: Vec<[f32; 3]> = opaque.into_iter().step_by(stride).collect();
+    let mut centers: Vec<[f32; 4]> = kmeans(&samples, k, seed).into_iter().map(rgba).collect();
+    centers.sort_by(|a, b| lightness(a).total_cmp(&lightness(b)));
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(from_stops(name, centers))
+}
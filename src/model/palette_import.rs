@@ -0,0 +1,153 @@
+//! Gradients from the classic fractal formats: Apophysis/Ultra Fractal .ugr (several gradients per file),
+//! flam3/Fractint .map (a line of RGB per color) and GIMP .gpl. All of them come out resampled to
+//! MAX_PALETTE_COLORS, with the file's own colors as-is, 0-255 scaled to 0-1.
+
+use std::fs;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use crate::model::palette::Palette;
+use crate::rendering::graphics_engine::MAX_PALETTE_COLORS;
+
+/// Ultra Fractal gradients have 400 slots, that wrap around
+const UGR_SLOTS: usize = 400;
+
+/// Every palette in the file, picked by extension
+pub fn load_palettes(path: &Path) -> Result<Vec<Palette>> {
+    let src = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read {}", path.display()))?;
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let palettes = match ext.as_str() {
+        "ugr" | "gradient" => parse_ugr(&src),
+        "map" => parse_map(&src, &name).map(|p| vec![p]),
+        "gpl" => parse_gpl(&src, &name).map(|p| vec![p]),
+        _ => bail!("Unknown palette format .{ext}, expected .ugr, .map or .gpl"),
+    };
+    palettes.with_context(|| format!("Couldn't load {}", path.display()))
+}
+
+fn rgb8(r: u8, g: u8, b: u8) -> [f32; 4] {
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+}
+
+/// Evenly spaced colors stretched over the whole palette
+fn resampled(name: &str, colors: Vec<[f32; 4]>) -> Palette {
+    let palette = Palette { name: name.to_owned(), rotation: 0, colors };
+    Palette { colors: palette.resample(MAX_PALETTE_COLORS), ..palette }
+}
+
+/// The first three numbers on a line, if it starts with them
+fn parse_rgb(line: &str) -> Option<[f32; 4]> {
+    let mut nums = line.split_whitespace().map(|t| t.parse::<u8>());
+    match (nums.next(), nums.next(), nums.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => Some(rgb8(r, g, b)),
+        _ => None,
+    }
+}
+
+/// `name { gradient: title="..." smooth=no index=0 color=123 ... }`, repeated.
+/// Colors are Windows COLORREFs, red in the low byte.
+pub fn parse_ugr(src: &str) -> Result<Vec<Palette>> {
+    let mut palettes = vec![];
+    let mut rest = src;
+    while let Some(open) = rest.find('{') {
+        let block_name = rest[..open].lines().last().unwrap_or_default().trim().to_owned();
+        let Some(close) = rest[open..].find('}') else {
+            bail!("Gradient {block_name} has no closing brace");
+        };
+        let body = &rest[open + 1..open + close];
+        rest = &rest[open + close + 1..];
+
+        let mut stops: Vec<(usize, [f32; 4])> = vec![];
+        let mut index = None;
+        for token in body.split_whitespace() {
+            let Some((key, value)) = token.split_once('=') else {
+                continue;
+            };
+            match key {
+                "index" => index = Some(value.parse::<usize>()
+                    .with_context(|| format!("Bad index {value} in gradient {block_name}"))?),
+                "color" => {
+                    let Some(i) = index.take() else {
+                        bail!("Color without an index in gradient {block_name}");
+                    };
+                    let c = value.parse::<u32>()
+                        .with_context(|| format!("Bad color {value} in gradient {block_name}"))?;
+                    stops.push((i, rgb8(c as u8, (c >> 8) as u8, (c >> 16) as u8)));
+                }
+                _ => {}
+            }
+        }
+        if stops.is_empty() {
+            bail!("Gradient {block_name} has no colors");
+        }
+        // titles can have spaces, so they're read separately from the rest
+        let title = body.find("title=\"").and_then(|start| body[start + 7..].split('"').next());
+        let name = title.filter(|t| !t.is_empty()).map_or(block_name, str::to_owned);
+        palettes.push(Palette {
+            name,
+            rotation: 0,
+            colors: sample_ugr_stops(stops, MAX_PALETTE_COLORS),
+        });
+    }
+    if palettes.is_empty() {
+        bail!("No gradients found");
+    }
+    Ok(palettes)
+}
+
+/// Linear between stops, wrapping from the last back around to the first
+fn sample_ugr_stops(mut stops: Vec<(usize, [f32; 4])>, n: usize) -> Vec<[f32; 4]> {
+    stops.sort_by_key(|(i, _)| *i);
+    let slots = UGR_SLOTS.max(stops.last().map_or(0, |(i, _)| i + 1)) as f32;
+    let positions: Vec<f32> = stops.iter().map(|(i, _)| *i as f32).collect();
+    (0..n)
+        .map(|k| {
+            let x = k as f32 / n as f32 * slots;
+            let next = positions.iter().position(|p| *p > x).unwrap_or(stops.len());
+            let (p0, c0) = match next {
+                0 => (positions[stops.len() - 1] - slots, stops[stops.len() - 1].1),
+                i => (positions[i - 1], stops[i - 1].1),
+            };
+            let (p1, c1) = match next {
+                i if i == stops.len() => (positions[0] + slots, stops[0].1),
+                i => (positions[i], stops[i].1),
+            };
+            let t = if p1 > p0 { (x - p0) / (p1 - p0) } else { 0.0 };
+            [0, 1, 2, 3].map(|j| c0[j] + (c1[j] - c0[j]) * t)
+        })
+        .collect()
+}
+
+/// One `r g b` per line, anything after them is a comment
+pub fn parse_map(src: &str, name: &str) -> Result<Palette> {
+    let colors: Vec<[f32; 4]> = src.lines().filter_map(parse_rgb).collect();
+    if colors.is_empty() {
+        bail!("No colors found");
+    }
+    Ok(resampled(name, colors))
+}
+
+/// `GIMP Palette`, then optional `Name:` and `Columns:` lines, `#` comments and `r g b name` colors
+pub fn parse_gpl(src: &str, name: &str) -> Result<Palette> {
+    let mut lines = src.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        bail!("Not a GIMP palette");
+    }
+    let mut name = name.to_owned();
+    let mut colors = vec![];
+    for line in lines {
+        let line = line.trim();
+        if let Some(n) = line.strip_prefix("Name:") {
+            name = n.trim().to_owned();
+        } else if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+            continue;
+        } else {
+            colors.push(parse_rgb(line).with_context(|| format!("Bad color line: {line}"))?);
+        }
+    }
+    if colors.is_empty() {
+        bail!("No colors found");
+    }
+    Ok(resampled(&name, colors))
+}
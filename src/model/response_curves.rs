@@ -0,0 +1,82 @@
+use std::hash::{Hash, Hasher};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Remaps the tone mapped image. Each curve goes through (input, output) points in [0,1], sorted by input,
+/// and is linear between them. Overall applies to red, green and blue before their own curves,
+/// alpha is how much of the background shows through.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ResponseCurves {
+    pub overall: Vec<[f32; 2]>,
+    pub red: Vec<[f32; 2]>,
+    pub green: Vec<[f32; 2]>,
+    pub blue: Vec<[f32; 2]>,
+    pub alpha: Vec<[f32; 2]>,
+}
+
+impl Default for ResponseCurves {
+    fn default() -> Self {
+        let identity = vec![[0.0, 0.0], [0.25, 0.25], [0.5, 0.5], [0.75, 0.75], [1.0, 1.0]];
+        Self {
+            overall: identity.clone(),
+            red: identity.clone(),
+            green: identity.clone(),
+            blue: identity.clone(),
+            alpha: identity,
+        }
+    }
+}
+
+impl Hash for ResponseCurves {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for curve in [&self.overall, &self.red, &self.green, &self.blue, &self.alpha] {
+            curve.len().hash(state);
+            for p in curve {
+                p.map(f32::to_bits).hash(state);
+            }
+        }
+    }
+}
+
+impl ResponseCurves {
+    /// The curve at `x`, flat past its first and last points. No points is the identity.
+    pub fn eval(curve: &[[f32; 2]], x: f32) -> f32 {
+        match curve.iter().position(|p| p[0] >= x) {
+            _ if curve.is_empty() => x,
+            None => curve[curve.len() - 1][1],
+            Some(0) => curve[0][1],
+            Some(i) => {
+                let ([x0, y0], [x1, y1]) = (curve[i - 1], curve[i]);
+                if x1 > x0 { y0 + (y1 - y0) * (x - x0) / (x1 - x0) } else { y1 }
+            }
+        }
+    }
+
+    /// For curves from a file: sorts each one by input, and fails on points outside [0,1]
+    pub fn sort_and_check(&mut self) -> Result<()> {
+        for (name, curve) in [("overall", &mut self.overall), ("red", &mut self.red), ("green", &mut self.green),
+                              ("blue", &mut self.blue), ("alpha", &mut self.alpha)] {
+            if let Some(p) = curve.iter().find(|p| !p.iter().all(|c| (0.0..=1.0).contains(c))) {
+                bail!("The {name} response curve has a point at {p:?}, outside 0 to 1");
+            }
+            curve.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        }
+        Ok(())
+    }
+
+    /// `n` evenly spaced rgba entries from 0 to 1 of input, what the render pass looks up
+    pub fn bake(&self, n: usize) -> Vec<[f32; 4]> {
+        (0..n)
+            .map(|i| {
+                let x = if n == 1 { 0.0 } else { i as f32 / (n - 1) as f32 };
+                let o = Self::eval(&self.overall, x);
+                [
+                    Self::eval(&self.red, o),
+                    Self::eval(&self.green, o),
+                    Self::eval(&self.blue, o),
+                    Self::eval(&self.alpha, x),
+                ].map(|c| c.clamp(0.0, 1.0))
+            })
+            .collect()
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use std::string::String;
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use toml::Spanned;
+use crate::util::glsl::glsl_to_wgsl;
 
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -12,44 +15,98 @@ pub struct Transform {
     pub includes: String,
     pub reference_url: String,
     pub source_code: String,
+    #[serde(default)]
+    pub source_line: usize, //line of the TOML source_code starts on, for error messages. 0 if unknown
     pub real_params: HashMap<String, f32>, //should be read-only
     pub vec3_params: HashMap<String, [f32; 3]> //likewise
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Wgsl {
-    src: String,
+    src: Spanned<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TransformSource {
     name: String,
-    version: String,
+    version: Spanned<toml::Value>, //see version_string
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
     tags: Vec<String>,
+    #[serde(default)]
+    reference_url: String,
     real_params: Option<HashMap<String, f32>>,
     vec3_params: Option<HashMap<String, [f32; 3]>>,
     wgsl: Wgsl,
 }
 
+/// Versions get written as `version = 1.1` about as often as `version = "1.1"`, accept both.
+/// A number is taken as it's written, going through a float would make 1.10 the same as 1.1.
+fn version_string(toml_src: &str, version: &Spanned<toml::Value>) -> Result<String> {
+    Ok(match version.get_ref() {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(_) | toml::Value::Float(_) => toml_src[version.span()].trim().to_string(),
+        other => bail!("version should be a string or a number, not {}", other.type_str()),
+    })
+}
+
+/// Multi-line strings drop the newline straight after their opening quotes, so the source starts a line later
+fn source_start_line(toml_src: &str, value_start: usize) -> usize {
+    let line = toml_src[..value_start].matches('\n').count() + 1;
+    let value = &toml_src[value_start..];
+    let multiline = value.starts_with("'''") || value.starts_with("\"\"\"");
+    if multiline && value[3..].trim_start_matches('\r').starts_with('\n') {
+        line + 1
+    } else {
+        line
+    }
+}
+
 impl Transform{
-    //async load from file
     pub fn cube() -> Self {
-        let src: TransformSource = toml::from_str(&include_str!("../../transforms/cube.toml")).unwrap();
+        Self::from_toml(include_str!("../../transforms/cube.toml")).unwrap()
+    }
 
-        let real_params = src.real_params.unwrap_or(HashMap::new());
-        let vec3_params = src.vec3_params.unwrap_or(HashMap::new());
+    pub fn from_toml(toml_src: &str) -> Result<Self> {
+        let src: TransformSource = toml::from_str(toml_src)?;
 
-        Self {
+        let real_params = src.real_params.unwrap_or_default();
+        let vec3_params = src.vec3_params.unwrap_or_default();
+        let param_names: Vec<&str> = real_params.keys().chain(vec3_params.keys()).map(|k| k.as_str()).collect();
+        //plugins ported from IFSRenderer are still GLSL, WGSL passes through unchanged
+        let source_code = glsl_to_wgsl(src.wgsl.src.get_ref(), &param_names);
+        let source_line = source_start_line(toml_src, src.wgsl.src.span().start);
+        let version = version_string(toml_src, &src.version)?;
+
+        Ok(Self {
             name: src.name,
-            version: src.version,
-            description: String::from("it's a cube"),
+            version,
+            description: src.description,
             tags: src.tags,
             includes: String::from(""),
-            reference_url: String::from(""),
-            source_code: src.wgsl.src,
+            reference_url: src.reference_url,
+            source_code,
+            source_line,
             real_params,
             vec3_params,
-        }
+        })
+    }
+}
+
+impl Transform {
+    /// Param names in the order they're packed into the real_params buffer
+    pub fn real_param_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.real_params.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Likewise for vec3_params
+    pub fn vec3_param_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.vec3_params.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
     }
 }
 
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::model::transform::Transform;
+
+/// A transform file that couldn't be loaded, and why
+#[derive(Clone, Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// Every transform we know about, indexed by name and version.
+/// Iterators and imported worlds look transforms up here instead of embedding paths to files.
+#[derive(Clone, Debug, Default)]
+pub struct TransformLibrary {
+    transforms: BTreeMap<(String, String), Transform>,
+    sources: BTreeMap<(String, String), PathBuf>,
+    pub errors: Vec<LoadError>,
+}
+
+impl TransformLibrary {
+    /// Scans each directory for *.toml TransformSources. Files that fail to parse end up in `errors`
+    /// rather than stopping the rest from loading.
+    pub fn load(dirs: &[PathBuf]) -> Self {
+        let mut lib = Self::default();
+        for dir in dirs {
+            lib.load_dir(dir);
+        }
+        lib
+    }
+
+    pub fn load_dir(&mut self, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.errors.push(LoadError { path: dir.to_path_buf(), message: e.to_string() });
+                return;
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort(); //so that which duplicate wins doesn't depend on the filesystem
+        for path in paths {
+            self.load_file(&path);
+        }
+    }
+
+    pub fn load_file(&mut self, path: &Path) {
+        let parsed = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|src| Transform::from_toml(&src).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(tf) => {
+                let key = (tf.name.clone(), tf.version.clone());
+                if let Some(first) = self.sources.get(&key) {
+                    self.errors.push(LoadError {
+                        path: path.to_path_buf(),
+                        message: format!("{} {} is already loaded from {}", key.0, key.1, first.display()),
+                    });
+                    return;
+                }
+                self.sources.insert(key.clone(), path.to_path_buf());
+                self.transforms.insert(key, tf);
+            }
+            Err(message) => self.errors.push(LoadError { path: path.to_path_buf(), message }),
+        }
+    }
+
+    pub fn insert(&mut self, tf: Transform) {
+        self.transforms.insert((tf.name.clone(), tf.version.clone()), tf);
+    }
+
+    pub fn get(&self, name: &str, version: &str) -> Option<&Transform> {
+        self.transforms.get(&(name.to_owned(), version.to_owned()))
+    }
+
+    /// The file a transform was read from, if it came from disk
+    pub fn source_path(&self, name: &str, version: &str) -> Option<&Path> {
+        self.sources.get(&(name.to_owned(), version.to_owned())).map(|p| p.as_path())
+    }
+
+    /// Sorted by name, then version
+    pub fn iter(&self) -> impl std::iter::Iterator<Item = &Transform> {
+        self.transforms.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::model::ifs::IFS;
+use crate::rendering::graphics_engine::MAX_ITERATORS;
+
+/// Bump this whenever a model change would make older files load wrong,
+/// and teach `from_world_toml` to upgrade the previous version.
+pub const WORLD_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct WorldFile {
+    format_version: u32,
+    world: IFS,
+}
+
+/// Read first, so a file from the future fails with a useful message instead of a missing-field error
+#[derive(Deserialize)]
+struct Header {
+    format_version: Option<u32>,
+}
+
+impl IFS {
+    pub fn to_world_toml(&self) -> Result<String> {
+        let file = WorldFile {
+            format_version: WORLD_FORMAT_VERSION,
+            world: self.clone(),
+        };
+        Ok(toml::to_string(&file)?)
+    }
+
+    pub fn from_world_toml(src: &str) -> Result<IFS> {
+        let header: Header = toml::from_str(src).context("Malformed world file")?;
+        let file: WorldFile = match header.format_version {
+            None => bail!("Not an IFSRS world file (no format_version)"),
+            Some(v) if v > WORLD_FORMAT_VERSION => bail!(
+                "World was saved by a newer IFSRS (format version {v}, this build reads up to {WORLD_FORMAT_VERSION})"),
+            Some(_) => toml::from_str(src).context("Malformed world file")?,
+        };
+
+        let mut ifs = file.world;
+        ifs.response_curves.sort_and_check()?;
+        if ifs.iterators.len() > MAX_ITERATORS {
+            bail!("The world has {} iterators, IFSRS can render up to {MAX_ITERATORS}", ifs.iterators.len());
+        }
+        ifs.claim_ids()?;
+        let ids: Vec<i32> = ifs.iterators.iter().map(|it| it.id).collect();
+        if let Some(e) = ifs.xaos.edges().find(|e| !ids.contains(&e.from) || !ids.contains(&e.to)) {
+            bail!("Weight between unknown iterators {} -> {}", e.from, e.to);
+        }
+        Ok(ifs)
+    }
+
+    pub fn save_world(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_world_toml()?)
+            .with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    pub fn load_world(path: &Path) -> Result<IFS> {
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_world_toml(&src)
+            .with_context(|| format!("Couldn't load {}", path.display()))
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use serde::{Deserialize, Serialize};
+
+/// Transition weights between iterators, keyed by iterator id. A missing edge has weight 0,
+/// which means points never go from one iterator to the other. Only nonzero weights are kept,
+/// so xaos that routes points the same compares, hashes and saves the same.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(from = "Vec<XaosEdge>", into = "Vec<XaosEdge>")]
+pub struct Xaos {
+    weights: BTreeMap<(i32, i32), f64>, //(from, to)
+}
+
+/// How xaos is stored on disk, tables can't have tuple keys
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct XaosEdge {
+    pub from: i32,
+    pub to: i32,
+    pub weight: f64,
+}
+
+impl From<Vec<XaosEdge>> for Xaos {
+    fn from(edges: Vec<XaosEdge>) -> Self {
+        let mut xaos = Xaos::default();
+        for e in edges {
+            xaos.set(e.from, e.to, e.weight);
+        }
+        xaos
+    }
+}
+
+impl From<Xaos> for Vec<XaosEdge> {
+    fn from(xaos: Xaos) -> Self {
+        xaos.edges().collect()
+    }
+}
+
+impl Hash for Xaos {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for ((from, to), w) in &self.weights {
+            (from, to, w.to_bits()).hash(state);
+        }
+    }
+}
+
+impl Index<(i32, i32)> for Xaos {
+    type Output = f64;
+    fn index(&self, (from, to): (i32, i32)) -> &Self::Output {
+        self.weights.get(&(from, to)).unwrap_or(&0.0)
+    }
+}
+
+impl Xaos {
+    pub fn get(&self, from: i32, to: i32) -> f64 {
+        self[(from, to)]
+    }
+
+    /// Setting a weight of 0 removes the edge
+    pub fn set(&mut self, from: i32, to: i32, weight: f64) {
+        if weight == 0.0 {
+            self.weights.remove(&(from, to));
+        } else {
+            self.weights.insert((from, to), weight);
+        }
+    }
+
+    /// Every nonzero edge, ordered by from, then to
+    pub fn edges(&self) -> impl std::iter::Iterator<Item = XaosEdge> + '_ {
+        self.weights.iter().map(|(&(from, to), &weight)| XaosEdge { from, to, weight })
+    }
+
+    /// (to, weight) for the nonzero edges leaving `from`
+    pub fn outgoing(&self, from: i32) -> impl std::iter::Iterator<Item = (i32, f64)> + '_ {
+        self.weights.range((from, i32::MIN)..=(from, i32::MAX)).map(|(&(_, to), &w)| (to, w))
+    }
+
+    /// (from, weight) for the nonzero edges arriving at `to`
+    pub fn incoming(&self, to: i32) -> impl std::iter::Iterator<Item = (i32, f64)> + '_ {
+        self.weights.iter()
+            .filter(move |((_, t), _)| *t == to)
+            .map(|(&(from, _), &w)| (from, w))
+    }
+
+    /// Scales the weights leaving `from` to sum to 1. Does nothing if there aren't any.
+    pub fn normalize(&mut self, from: i32) {
+        let sum: f64 = self.outgoing(from).map(|(_, w)| w).sum();
+        if sum == 0.0 {
+            return;
+        }
+        for ((f, _), w) in self.weights.iter_mut() {
+            if *f == from {
+                *w /= sum;
+            }
+        }
+    }
+
+    /// Drops every edge to or from `id`
+    pub fn remove_iterator(&mut self, id: i32) {
+        self.weights.retain(|(from, to), _| *from != id && *to != id);
+    }
+
+    /// Drops edges that mention ids not in `ids`
+    pub fn retain_iterators(&mut self, ids: &[i32]) {
+        self.weights.retain(|(from, to), _| ids.contains(from) && ids.contains(to));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+}
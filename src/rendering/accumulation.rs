@@ -0,0 +1,165 @@
+//! Raw accumulations, the float histogram and the point states with what it takes to carry on from them,
+//! so a long render can be saved and resumed, or runs with different seeds merged into one.
+//!
+//! The file is a small header, then the histogram and point states deflated. The world itself isn't in it,
+//! only IFS::histogram_digest, which takes in the size too.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use crate::rendering::pipeline_compute::POINTS_STATE_SIZE;
+
+const MAGIC: &[u8; 8] = b"IFSRSACC";
+const VERSION: u32 = 3; //1 had no accepted sample count, 2 a hash that changed between builds
+const MAX_SIDE: u32 = 1 << 15; //more than any GPU's textures
+const MAX_PIXELS: usize = 1 << 27; //a 2 GiB histogram, more than GPUs bind as one buffer
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Accumulation {
+    pub world_digest: u64, //IFS::histogram_digest of what was rendered
+    pub width: u32,
+    pub height: u32,
+    pub seed: u32,
+    pub invocation_iters: i32,
+    pub dispatch_count: u64,
+    pub accepted_samples: u64, //hits that reached the histogram, for the sampling level
+    pub histogram: Vec<[f32; 4]>,
+    pub points_state: Vec<u8>, //as the kernel left it, only good for carrying on with the same seed
+}
+
+impl Accumulation {
+    /// Splits a readback of the histogram followed by the point states, see GpuRenderer::read_accumulation
+    pub fn fill(&mut self, bytes: &[u8]) -> Result<()> {
+        let histogram_bytes = self.width as usize * self.height as usize * size_of::<[f32; 4]>();
+        if bytes.len() < histogram_bytes {
+            bail!("Expected a {}x{} histogram, got {} bytes", self.width, self.height, bytes.len());
+        }
+        self.histogram = bytes[..histogram_bytes].chunks_exact(size_of::<[f32; 4]>())
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+        self.points_state = bytes[histogram_bytes..].to_vec();
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+        self.write(BufWriter::new(file))
+            .with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+        Self::read(BufReader::new(file))
+            .with_context(|| format!("Couldn't read {}", path.display()))
+    }
+
+    fn write(&self, mut out: impl Write) -> Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&self.world_digest.to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&self.invocation_iters.to_le_bytes())?;
+        out.write_all(&self.dispatch_count.to_le_bytes())?;
+        out.write_all(&self.accepted_samples.to_le_bytes())?;
+        out.write_all(&(self.points_state.len() as u64).to_le_bytes())?;
+
+        let mut deflated = ZlibEncoder::new(out, Compression::fast());
+        deflated.write_all(bytemuck::cast_slice(&self.histogram))?;
+        deflated.write_all(&self.points_state)?;
+        deflated.finish()?.flush()?;
+        Ok(())
+    }
+
+    fn read(mut src: impl Read) -> Result<Self> {
+        fn array<const N: usize>(src: &mut impl Read) -> Result<[u8; N]> {
+            let mut bytes = [0; N];
+            src.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+
+        if &array::<8>(&mut src)? != MAGIC {
+            bail!("Not an accumulation file");
+        }
+        let version = u32::from_le_bytes(array(&mut src)?);
+        if version != VERSION {
+            bail!("Accumulation file version {version} isn't supported");
+        }
+        let mut acc = Self {
+            world_digest: u64::from_le_bytes(array(&mut src)?),
+            width: u32::from_le_bytes(array(&mut src)?),
+            height: u32::from_le_bytes(array(&mut src)?),
+            seed: u32::from_le_bytes(array(&mut src)?),
+            invocation_iters: i32::from_le_bytes(array(&mut src)?),
+            dispatch_count: u64::from_le_bytes(array(&mut src)?),
+            accepted_samples: u64::from_le_bytes(array(&mut src)?),
+            ..Self::default()
+        };
+        let points_len = u64::from_le_bytes(array(&mut src)?);
+        if acc.width > MAX_SIDE || acc.height > MAX_SIDE {
+            bail!("It's {}x{}, larger than any render", acc.width, acc.height);
+        }
+        let pixels = acc.width as usize * acc.height as usize;
+        if pixels > MAX_PIXELS {
+            bail!("It's {}x{}, larger than any render", acc.width, acc.height);
+        }
+        if points_len != POINTS_STATE_SIZE as u64 {
+            bail!("It has {points_len} bytes of point states, this build expects {POINTS_STATE_SIZE}");
+        }
+
+        //a row at a time, so what's allocated follows what's really there rather than what the header says
+        let mut inflated = ZlibDecoder::new(src);
+        let mut row = vec![[0f32; 4]; acc.width as usize];
+        for _ in 0..acc.height {
+            inflated.read_exact(bytemuck::cast_slice_mut(&mut row)).context("The histogram is cut short")?;
+            acc.histogram.extend_from_slice(&row);
+        }
+        acc.points_state = vec![0; POINTS_STATE_SIZE];
+        inflated.read_exact(&mut acc.points_state).context("The point states are cut short")?;
+        Ok(acc)
+    }
+
+    /// Adds another run of the same world into this one. The point states and seed stay this run's.
+    pub fn merge(&mut self, other: &Accumulation) -> Result<()> {
+        if other.world_digest != self.world_digest {
+            bail!("They were rendered from different worlds");
+        }
+        if (other.width, other.height) != (self.width, self.height) {
+            bail!("One is {}x{}, the other {}x{}", self.width, self.height, other.width, other.height);
+        }
+        if other.invocation_iters != self.invocation_iters {
+            bail!("They were rendered with {} and {} iterations per invocation", self.invocation_iters, other.invocation_iters);
+        }
+        if other.seed == self.seed {
+            bail!("Both were rendered with seed {}, so they're the same samples", self.seed);
+        }
+        for (a, b) in self.histogram.iter_mut().zip(&other.histogram) {
+            for (x, y) in a.iter_mut().zip(b) {
+                *x += y;
+            }
+        }
+        self.dispatch_count += other.dispatch_count;
+        self.accepted_samples += other.accepted_samples;
+        Ok(())
+    }
+
+    /// Loads and merges the files, in order
+    pub fn load_merged(paths: &[impl AsRef<Path>]) -> Result<Self> {
+        let Some((first, rest)) = paths.split_first() else {
+            bail!("No accumulations given");
+        };
+        let mut acc = Self::load(first.as_ref())?;
+        for path in rest {
+            let path = path.as_ref();
+            acc.merge(&Self::load(path)?)
+                .with_context(|| format!("Couldn't merge {} into {}", path.display(), first.as_ref().display()))?;
+        }
+        Ok(acc)
+    }
+}
@@ -0,0 +1,468 @@
+//! The chaos game from ifs_kernel.wgsl, on the CPU. Slow, but it runs where there's no GPU, and it's
+//! the reference the kernel is checked against. Each thread runs one point, like one kernel invocation,
+//! into a histogram of its own, and they are summed when read into one laid out the same as the GPU's:
+//! rgba per pixel, row major.
+//!
+//! Transforms are WGSL, so they can't run here. Only the ones in PORTED_TRANSFORMS are supported, ported by hand
+//! in native_transform, and an IFS using any other transform or version is refused. Ports are matched on name and
+//! version alone, an edited source_code still runs the port as it was written. test_ported_transforms_unchanged
+//! fails when one in transforms/ changes, so the port gets brought in line.
+
+use std::f32::consts::TAU;
+use std::thread;
+use anyhow::{bail, Result};
+use nalgebra::{Matrix3, Matrix4, Vector3, Vector4};
+use crate::alias_method::{start_alias_table, xaos_alias_tables, AliasEntry};
+use crate::model::ifs::IFS;
+use crate::model::iterator::Iterator;
+use crate::rendering::gpu_structs::CameraStruct;
+use crate::rendering::graphics_engine::MAX_PALETTE_COLORS;
+
+type TransformFn = Box<dyn Fn(Vector3<f32>, &mut Rng) -> Vector3<f32> + Send + Sync>;
+
+/// The kernel's random(). Walker 0 draws the same sequence one kernel invocation would on its own;
+/// the others are decorrelated through the constant the kernel hashes in.
+struct Rng {
+    seed: u32,
+    stream: u32,
+    dispatch_cnt: u32,
+    next_sample: u32,
+}
+
+impl Rng {
+    fn random(&mut self) -> f32 {
+        self.next_sample = self.next_sample.wrapping_add(1);
+        f_hash4(self.seed, self.stream, self.dispatch_cnt, self.next_sample)
+    }
+}
+
+fn pcg_hash_4d(v: [u32; 4]) -> [u32; 4] {
+    let mut v = v.map(|x| x.wrapping_mul(1664525).wrapping_add(1013904223));
+    for round in 0..2 {
+        v[0] = v[0].wrapping_add(v[1].wrapping_mul(v[3]));
+        v[1] = v[1].wrapping_add(v[2].wrapping_mul(v[0]));
+        v[2] = v[2].wrapping_add(v[0].wrapping_mul(v[1]));
+        v[3] = v[3].wrapping_add(v[1].wrapping_mul(v[2]));
+        if round == 0 {
+            v = v.map(|x| x ^ (x >> 16));
+        }
+    }
+    v
+}
+
+fn xorshift128(v: [u32; 4]) -> u32 {
+    let mut w = v[3];
+    w ^= w << 11;
+    w ^= w >> 8;
+    //after the wxyz swizzle, x is w and y is x
+    w ^= v[0];
+    w ^= v[0] >> 19;
+    w
+}
+
+/// Uniform in [0,1) from the mantissa bits
+fn f_hash(h: u32) -> f32 {
+    f32::from_bits((h & 0x007FFFFF) | 0x3F800000) - 1.0
+}
+
+fn f_hash4(u1: u32, u2: u32, u3: u32, u4: u32) -> f32 {
+    f_hash(xorshift128(pcg_hash_4d([u1, u2, u3, u4])))
+}
+
+/// WGSL's round
+fn round_ties_even(x: f32) -> f32 {
+    if (x - x.trunc()).abs() == 0.5 {
+        2.0 * (x / 2.0).round()
+    } else {
+        x.round()
+    }
+}
+
+fn mix3(a: Vector3<f32>, b: Vector3<f32>, t: f32) -> Vector3<f32> {
+    a * (1.0 - t) + b * t
+}
+
+/// mat3x3 from the kernel, its arguments are column major
+fn rotmat(v: Vector3<f32>, arad: f32) -> Matrix3<f32> {
+    let (s, c) = arad.sin_cos();
+    Matrix3::from_column_slice(&[
+        c + (1.0 - c) * v.x * v.x, (1.0 - c) * v.x * v.y - s * v.z, (1.0 - c) * v.x * v.z + s * v.y,
+        (1.0 - c) * v.x * v.y + s * v.z, c + (1.0 - c) * v.y * v.y, (1.0 - c) * v.y * v.z - s * v.x,
+        (1.0 - c) * v.x * v.z - s * v.y, (1.0 - c) * v.y * v.z + s * v.x, c + (1.0 - c) * v.z * v.z,
+    ])
+}
+
+fn rotate_euler(euler_angles: Vector3<f32>) -> Matrix3<f32> {
+    rotmat(Vector3::x(), euler_angles.x)
+        * rotmat(Vector3::y(), euler_angles.y)
+        * rotmat(Vector3::z(), euler_angles.z)
+}
+
+/// The transforms native_transform has ports of, by name and version. The CPU renderer supports these and nothing else.
+pub const PORTED_TRANSFORMS: &[(&str, &str)] = &[
+    ("Cube", "1.1"),
+    ("Checks", "1.1"),
+    ("Affine", "1.1"),
+];
+
+/// Ports of the transforms in transforms/, see PORTED_TRANSFORMS. Params are resolved once, the same way the engine packs them.
+fn native_transform(it: &Iterator) -> Option<TransformFn> {
+    let tf = &it.transform;
+    if !PORTED_TRANSFORMS.iter().any(|(name, version)| tf.name == *name && tf.version == *version) {
+        return None;
+    }
+    let real = |name: &str| it.real_params.get(name).or(tf.real_params.get(name)).copied().unwrap_or_default();
+    let vec3 = |name: &str| Vector3::from(it.vec3_params.get(name).or(tf.vec3_params.get(name)).copied().unwrap_or_default());
+
+    match tf.name.as_str() {
+        "Cube" => Some(Box::new(|_, rng| {
+            let x = 0.5 - rng.random();
+            let y = 0.5 - rng.random();
+            let z = 0.5 - rng.random();
+            Vector3::new(x, y, z)
+        })),
+        "Checks" => {
+            let (size, offset) = (real("Size"), vec3("Offset"));
+            Some(Box::new(move |p, _| {
+                let checks = (p * size).add_scalar(0.5).map(f32::floor);
+                let checked = checks.x + checks.y + checks.z;
+                if checked - 2.0 * (checked / 2.0).floor() != 0.0 {
+                    p - offset
+                } else {
+                    p + offset
+                }
+            }))
+        }
+        "Affine" => {
+            let scale = Matrix3::from_diagonal(&vec3("Scale"));
+            // p * M in WGSL is M^T * p
+            let m = (rotate_euler(vec3("Rotate") * std::f32::consts::PI / 180.0) * scale).transpose();
+            let translate = vec3("Translate");
+            Some(Box::new(move |p, _| translate + m * p))
+        }
+        _ => None,
+    }
+}
+
+/// Iterator as the kernel sees it
+struct CpuIterator {
+    transform: TransformFn,
+    color_speed: f32,
+    color_index: f32,
+    opacity: f32,
+    shading_mode: i32,
+    tf_mix: f32,
+    tf_add: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PointState {
+    pos: Vector3<f32>,
+    color_index: f32,
+    iterator_index: i32,
+    iteration_depth: i32,
+}
+
+/// Everything that stays fixed while rendering, the kernel's uniforms
+struct Scene {
+    iterators: Vec<CpuIterator>,
+    start_table: Vec<AliasEntry>,
+    xaos_tables: Vec<AliasEntry>,
+    palette: Vec<[f32; 4]>,
+    camera: CameraStruct,
+    view_proj: Matrix4<f32>,
+    fog_effect: f32,
+    warmup: u32,
+    entropy: f32,
+    width: u32,
+    height: u32,
+}
+
+impl Scene {
+    fn itnum(&self) -> usize {
+        self.iterators.len()
+    }
+
+    fn alias_sample(table: &[AliasEntry], itnum: usize, r01: f32) -> i32 {
+        let i = (itnum as f32 * r01).floor() as usize;
+        let y = (itnum as f32 * r01).fract();
+        let entry = table[i.min(itnum - 1)];
+        if y < entry.prob {
+            return i as i32;
+        }
+        entry.alias
+    }
+
+    fn reset_state(&self, rng: &mut Rng) -> PointState {
+        let theta = TAU * rng.random();
+        let phi = (2.0 * rng.random() - 1.0).acos();
+        let mut rho = starting_distribution(rng.random());
+        rho *= 2.0 * Vector3::new(self.camera.position[0], self.camera.position[1], self.camera.position[2]).norm();
+        let iterator_index = Self::alias_sample(&self.start_table, self.itnum(), rng.random());
+        PointState {
+            pos: Vector3::new(rho * phi.sin() * theta.cos(), rho * phi.sin() * theta.sin(), rho * phi.cos()),
+            color_index: self.iterators[iterator_index as usize].color_index,
+            iterator_index,
+            iteration_depth: 0,
+        }
+    }
+
+    fn apply_coloring(it: &CpuIterator, p0: Vector3<f32>, p: Vector3<f32>, color_index: f32) -> f32 {
+        let mut speed = it.color_speed;
+        if it.shading_mode == 1 {
+            let p_delta = (p - p0).norm();
+            speed *= 1.0 - 1.0 / (1.0 + p_delta);
+        }
+        let new_index = color_index * (1.0 - speed) + it.color_index * speed;
+        if new_index != 0.0 && new_index.fract() == 0.0 {
+            new_index
+        } else {
+            new_index - new_index.floor()
+        }
+    }
+
+    fn palette_color(&self, pos: f32) -> Vector3<f32> {
+        let cnt = self.palette.len();
+        let palettepos = pos * (cnt - 1) as f32;
+        let index = (palettepos.floor().max(0.0) as usize).min(cnt - 1);
+        let c1 = Vector3::new(self.palette[index][0], self.palette[index][1], self.palette[index][2]);
+        if index + 1 == cnt {
+            return c1;
+        }
+        let c2 = Vector3::new(self.palette[index + 1][0], self.palette[index + 1][1], self.palette[index + 1][2]);
+        mix3(c1, c2, palettepos - palettepos.floor())
+    }
+
+    fn defocus(&self, pos: Vector3<f32>) -> f32 {
+        let c = &self.camera;
+        let focus = Vector3::new(c.focus_point[0], c.focus_point[1], c.focus_point[2]);
+        let forward = Vector3::new(c.forward[0], c.forward[1], c.forward[2]);
+        0f32.max((pos - focus).dot(&-forward).abs() - c.depth_of_field)
+    }
+
+    /// Pixel coordinates, or None if the point is out of frame or behind the camera
+    fn project_perspective(&self, pos: Vector3<f32>, rng: &mut Rng) -> Option<(f32, f32)> {
+        let mut p_norm = self.view_proj * Vector4::new(pos.x, pos.y, pos.z, 1.0);
+
+        //discard behind camera
+        let dir = p_norm.xyz().normalize();
+        if dir.dot(&Vector3::new(0.0, 0.0, -1.0)) < 0.0 {
+            return None;
+        }
+        //the kernel only catches points with every component at infinity
+        if p_norm.iter().all(|c| !c.is_finite()) || p_norm.w == 0.0 {
+            return None;
+        }
+        p_norm /= p_norm.w;
+
+        //dof
+        let blur = self.camera.aperture * self.defocus(pos);
+        let ra = rng.random();
+        let rl = rng.random();
+        p_norm.x += rl.sqrt() * blur * (ra * TAU).cos();
+        p_norm.y += rl.sqrt() * blur * (ra * TAU).sin();
+
+        //discard at edges
+        let cl = (p_norm.x.clamp(-1.0, 1.0), p_norm.y.clamp(-1.0, 1.0));
+        if (p_norm.x - cl.0, p_norm.y - cl.1) != (0.0, 0.0) {
+            return None;
+        }
+
+        let ratio = self.width as f32 / self.height as f32;
+        Some((
+            (p_norm.x + 1.0) * 0.5 * self.width as f32 - 0.5,
+            (p_norm.y * ratio + 1.0) * 0.5 * self.height as f32 - 0.5,
+        ))
+    }
+
+    /// One kernel invocation: `iters` steps of the point, plotted into `histogram`
+    fn run(&self, p: &mut PointState, rng: &mut Rng, iters: u32, histogram: &mut [[f32; 4]]) {
+        for _ in 0..iters {
+            //pick a random xaos weighted Transform index
+            let r = rng.random();
+            let r_index = Self::alias_sample(&self.xaos_tables[p.iterator_index as usize * self.itnum()..], self.itnum(), r);
+            if r_index == -1 || p.iteration_depth == -1 || rng.random() < self.entropy {
+                *p = self.reset_state(rng);
+            } else {
+                p.iterator_index = r_index;
+            }
+
+            let it = &self.iterators[p.iterator_index as usize];
+            let p0 = p.pos;
+            let p_ret = (it.transform)(p0, rng);
+            p.pos = mix3(p0, p_ret + p0 * it.tf_add, it.tf_mix);
+
+            if p.pos.dot(&p.pos) == 0.0 {
+                p.iteration_depth = -1; //means invalid
+                continue;
+            }
+
+            p.color_index = Self::apply_coloring(it, p0, p.pos, p.color_index);
+            p.iteration_depth += 1;
+
+            if p.iteration_depth < self.warmup as i32 || it.opacity == 0.0 {
+                continue;
+            }
+
+            let Some((x, y)) = self.project_perspective(p.pos, rng) else {
+                continue; //out of frame
+            };
+            // WGSL rounds half to even. Unlike the kernel, pixels that round past the edge are dropped,
+            // rather than landing on the next row or outside the buffer.
+            let (x, y) = (round_ties_even(x) as i64, round_ties_even(y) as i64);
+            if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                continue;
+            }
+
+            let c = self.palette_color(p.color_index);
+            let mut color = [c.x, c.y, c.z, 0.0005];
+
+            let defocus = self.defocus(p.pos);
+            if self.fog_effect > 0.0 {
+                //optional fog effect
+                let fog_mask = 2.0 * (1.0 - 1.0 / (1.0 + (1.0 + self.fog_effect).powf(-defocus + self.camera.depth_of_field)));
+                color[3] *= fog_mask.clamp(0.0, 1.0);
+            }
+            if color[3] == 0.0 {
+                continue;
+            }
+            //mark area in focus with red
+            if defocus < 0.01 {
+                color = [1.0, 0.001, 0.0, 0.001];
+            }
+            for k in 0..3 {
+                color[k] *= color[3];
+            }
+
+            //the engine always runs with max_filter_radius 0, so the kernel's filter branch isn't ported
+            let px = &mut histogram[x as usize + y as usize * self.width as usize];
+            for k in 0..4 {
+                px[k] += color[k];
+            }
+        }
+    }
+}
+
+//from [0,1] uniform to [0,inf] ln
+fn starting_distribution(uniform_r: f32) -> f32 {
+    let a = uniform_r.powf(1.0 / 3.0); //avoid center of sphere
+    let curve = 1.578425; //half of the values are < 0.5
+    -1.0 / curve * (1.0 - a).ln()
+}
+
+/// One thread's point, and the histogram it plots into across dispatches
+struct Walker {
+    point: PointState,
+    rng: Rng,
+    histogram: Vec<[f32; 4]>,
+}
+
+pub struct CpuRenderer {
+    scene: Scene,
+    walkers: Vec<Walker>, //one per thread
+    dispatch_count: i32,
+}
+
+impl CpuRenderer {
+    /// Fails if the IFS has no iterators, or uses a transform not in PORTED_TRANSFORMS
+    pub fn new(model: &IFS, threads: usize, seed: u32) -> Result<Self> {
+        if model.iterators.is_empty() {
+            bail!("Nothing to render, the IFS has no iterators");
+        }
+        if model.width == 0 || model.height == 0 {
+            bail!("Can't render at {}x{}", model.width, model.height);
+        }
+        let mut iterators = vec![];
+        for it in &model.iterators {
+            let Some(transform) = native_transform(it) else {
+                bail!("Iterator {} uses {} {}, which has no CPU implementation of that version",
+                    it.id, it.transform.name, it.transform.version);
+            };
+            iterators.push(CpuIterator {
+                transform,
+                color_speed: it.color_speed,
+                color_index: it.color_index,
+                opacity: it.opacity,
+                shading_mode: it.shading_mode,
+                tf_mix: it.mix,
+                tf_add: it.add,
+            });
+        }
+        let palette = model.palette.resample(MAX_PALETTE_COLORS);
+        let camera = model.camera.clone().create_camera_struct();
+
+        let scene = Scene {
+            iterators,
+            start_table: start_alias_table(model),
+            xaos_tables: xaos_alias_tables(model),
+            palette,
+            view_proj: Matrix4::from(camera.view_proj_mat),
+            camera,
+            fog_effect: model.fog_effect,
+            warmup: model.fuse,
+            entropy: model.entropy,
+            width: model.width,
+            height: model.height,
+        };
+        let walkers = (0..threads.max(1) as u32)
+            .map(|i| Walker {
+                point: PointState::default(),
+                rng: Rng { seed, stream: 0xDEADBEEF ^ i, dispatch_cnt: 0, next_sample: 0 },
+                histogram: vec![[0.0; 4]; (model.width * model.height) as usize],
+            })
+            .collect();
+
+        Ok(Self {
+            scene,
+            walkers,
+            dispatch_count: 0,
+        })
+    }
+
+    /// Runs every point for `iters` steps, on a thread each. Points carry over between dispatches,
+    /// the first one starts them off with reset_state.
+    pub fn dispatch(&mut self, iters: u32) {
+        let scene = &self.scene;
+        let first = self.dispatch_count == 0;
+        let dispatch_cnt = self.dispatch_count as u32;
+
+        thread::scope(|s| {
+            for w in &mut self.walkers {
+                s.spawn(move || {
+                    w.rng.dispatch_cnt = dispatch_cnt;
+                    if first {
+                        w.point = scene.reset_state(&mut w.rng);
+                    }
+                    scene.run(&mut w.point, &mut w.rng, iters, &mut w.histogram);
+                });
+            }
+        });
+        self.dispatch_count += 1;
+    }
+
+    /// The threads' histograms summed. In thread order, so a given seed and thread count always gives the same one.
+    pub fn histogram(&self) -> Vec<[f32; 4]> {
+        let (first, rest) = self.walkers.split_first().expect("there's always a thread");
+        let mut histogram = first.histogram.clone();
+        for w in rest {
+            for (acc, px) in histogram.iter_mut().zip(&w.histogram) {
+                for k in 0..4 {
+                    acc[k] += px[k];
+                }
+            }
+        }
+        histogram
+    }
+
+    pub fn dispatch_count(&self) -> i32 {
+        self.dispatch_count
+    }
+
+    pub fn width(&self) -> u32 {
+        self.scene.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.scene.height
+    }
+}
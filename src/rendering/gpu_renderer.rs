@@ -0,0 +1,723 @@
+//! Runs the kernel on any wgpu device. The app drives it through GraphicsEngine, which shows the
+//! output texture in egui; ifsrs-render drives it on a device of its own, with no window at all.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use anyhow::{anyhow, bail, Context, Result};
+use egui_wgpu::RenderState;
+use wgpu::*;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use crate::alias_method::{start_alias_table, xaos_alias_tables};
+use crate::model::ifs::IFS;
+use crate::model::transform::Transform;
+use crate::rendering::accumulation::Accumulation;
+use crate::rendering::gpu_structs::*;
+use crate::rendering::graphics_engine::*;
+use crate::rendering::pipeline_compute::{Compute, WORKGROUP_SIZE};
+use crate::rendering::pipeline_render::Render;
+use crate::rendering::transform_validation::*;
+use crate::util::lru_cache::LruCache;
+use crate::util::math_extensions::linear_to_srgb;
+
+const KERNEL_SRC: &str = include_str!("ifs_kernel.wgsl");
+
+const KERNEL_CACHE_SIZE: usize = 8;
+
+/// Pipelines built from one combination of transforms
+#[derive(Clone)]
+struct CompiledKernel {
+    compute: Arc<ComputePipeline>,
+    render: Arc<RenderPipeline>,
+}
+
+/// Identifies the kernel built for these transforms, in tf_id order. Param values live in buffers,
+/// so only their names matter.
+fn kernel_key(transforms: &[&Transform]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for tf in transforms {
+        tf.name.hash(&mut hasher);
+        tf.version.hash(&mut hasher);
+        tf.source_code.hash(&mut hasher);
+        tf.real_param_names().hash(&mut hasher);
+        tf.vec3_param_names().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+
+/// The device and queue everything is created on and submitted to
+#[derive(Clone)]
+pub struct Gpu {
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+}
+
+impl From<&RenderState> for Gpu {
+    fn from(wgpu: &RenderState) -> Self {
+        Self { device: wgpu.device.clone(), queue: wgpu.queue.clone() }
+    }
+}
+
+impl Gpu {
+    /// Every adapter wgpu can find, in the order `headless` indexes them
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn adapters() -> Vec<AdapterInfo> {
+        Instance::default().enumerate_adapters(Backends::all()).iter().map(|a| a.get_info()).collect()
+    }
+
+    /// A device with no surface. `adapter` is an index into `adapters()` or part of an adapter's name,
+    /// e.g. "llvmpipe" for lavapipe. Without one, wgpu picks, falling back to whatever adapter exists.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn headless(adapter: Option<&str>) -> Result<Self> {
+        let instance = Instance::default();
+        let mut adapters = instance.enumerate_adapters(Backends::all());
+        let adapter = match adapter {
+            Some(choice) => {
+                let found = match choice.parse::<usize>() {
+                    Ok(i) if i < adapters.len() => Some(i),
+                    _ => adapters.iter().position(|a| a.get_info().name.to_lowercase().contains(&choice.to_lowercase())),
+                };
+                match found {
+                    Some(i) => adapters.swap_remove(i),
+                    None => bail!("No adapter matches \"{choice}\""),
+                }
+            }
+            None => futures::executor::block_on(instance.request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            }))
+                .or_else(|| (!adapters.is_empty()).then(|| adapters.swap_remove(0)))
+                .ok_or_else(|| anyhow!("No graphics adapter found"))?,
+        };
+        let info = adapter.get_info();
+        log::info!("Using {} ({:?}, {:?})", info.name, info.device_type, info.backend);
+
+        let (device, queue) = futures::executor::block_on(adapter.request_device(&DeviceDescriptor {
+            label: Some("IFSRS headless device"),
+            required_features: Features::empty(),
+            required_limits: adapter.limits(),
+        }, None)).with_context(|| format!("Couldn't open {}", info.name))?;
+        Ok(Self { device: Arc::new(device), queue: Arc::new(queue) })
+    }
+}
+
+/// A copy on its way back from the GPU. Polling the device moves it along.
+pub struct Readback {
+    buffer: Buffer,
+    mapped: Receiver<std::result::Result<(), BufferAsyncError>>,
+}
+
+impl Readback {
+    /// `buffer` needs MAP_READ, and the copy into it submitted already
+    fn start(buffer: Buffer) -> Self {
+        let (tx, rx) = channel();
+        buffer.slice(..).map_async(MapMode::Read, move |r| { let _ = tx.send(r); });
+        Self { buffer, mapped: rx }
+    }
+
+    /// The buffer's bytes once they've arrived, None until then
+    pub fn try_take(&self) -> Option<Result<Vec<u8>>> {
+        match self.mapped.try_recv() {
+            Ok(Ok(())) => {
+                let bytes = self.buffer.slice(..).get_mapped_range().to_vec();
+                self.buffer.unmap();
+                Some(Ok(bytes))
+            }
+            Ok(Err(e)) => Some(Err(anyhow!(e).context("Couldn't map a buffer for reading"))),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(anyhow!("A readback was dropped before it finished"))),
+        }
+    }
+}
+
+pub struct GpuRenderer {
+    pub compute_pipeline: Compute,
+    pub render_pipeline: Render,
+    pub shader: ShaderModule,
+    pub seed: u32,
+    pub invocation_iters: i32, //per invocation, per dispatch
+
+    validated: HashMap<u64, Result<(), TransformError>>, //by kernel and transform source, so slider drags don't revalidate
+    kernels: LruCache<u64, CompiledKernel>, //by the transforms in each tf_id slot, see kernel_key
+    kernel_key: Option<u64>, //of the pipelines in use
+    dispatch_count: i32, //since the histogram was last cleared
+    accepted_samples: u64, //since the histogram was last cleared, as of the last readback
+    samples_seen: u32, //what the wrapping counter on the GPU read last time
+    samples_readback: Option<Readback>,
+    model: IFS,
+    model_hash: Option<u64>, //of what's in the histogram, display settings aside
+    model_digest: Option<u64>, //the same as IFS::histogram_digest, for accumulations
+    errors: Vec<TransformError>, //from the last model that reached the kernel
+}
+
+impl GpuRenderer {
+    pub fn new(wgpu: &Gpu) -> Self {
+        let shader_desc = wgpu::include_wgsl!("ifs_kernel.wgsl");
+        let shader = wgpu.device.create_shader_module(shader_desc);
+
+        let compute = Compute::init(wgpu, &shader);
+
+        // TODO: unfuck that lol
+        let render = Render::init(wgpu, &shader, compute.bind_group_layout.clone(), compute.bind_group.clone(), (1920, 1080));
+
+        Self {
+            compute_pipeline: compute,
+            render_pipeline: render,
+            shader,
+            seed: DEFAULT_SEED,
+            invocation_iters: 512,
+            validated: HashMap::new(),
+            kernels: LruCache::new(KERNEL_CACHE_SIZE),
+            kernel_key: None,
+            dispatch_count: 0,
+            accepted_samples: 0,
+            samples_seen: 0,
+            samples_readback: None,
+            model: Default::default(),
+            model_hash: None,
+            model_digest: None,
+            errors: vec![],
+        }
+    }
+
+    pub fn model(&self) -> &IFS {
+        &self.model
+    }
+
+    /// Runs the kernel once and draws the output texture
+    pub fn dispatch(&mut self, wgpu: &Gpu) {
+        self.update_accepted_samples();
+        wgpu.queue.write_buffer(&self.compute_pipeline.parameters_buffer, 0 as BufferAddress, bytemuck::cast_slice(&[ParametersStruct {
+            seed: self.seed,
+            width: self.model.width,
+            height: self.model.height,
+            dispatch_cnt: self.dispatch_count,
+            reset_points_state: 0, // TODO: ??????
+            invocation_iters: self.invocation_iters,
+            padding_1: 0,
+            padding_2: 0,
+        }]));
+
+        self.dispatch_count += 1;
+        self.render_pipeline.write_tone_map(wgpu, &self.tone_map());
+
+        let compute_cmd = self.compute_pipeline.encode_commands(wgpu);
+        let render_cmd = self.render_pipeline.encode_commands(wgpu);
+        if self.samples_readback.is_some() {
+            wgpu.queue.submit([compute_cmd, render_cmd]);
+            return;
+        }
+        // counts what this dispatch accepted, once it's back
+        let readback = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Accepted samples readback"),
+            size: size_of::<u32>() as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = wgpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Accepted samples readback") });
+        encoder.copy_buffer_to_buffer(&self.compute_pipeline.accepted_samples_buffer, 0, &readback, 0, size_of::<u32>() as BufferAddress);
+        wgpu.queue.submit([compute_cmd, encoder.finish(), render_cmd]);
+        self.samples_readback = Some(Readback::start(readback));
+    }
+
+    /// Draws the output texture again without running the kernel, for display settings changed while stopped
+    pub fn redraw(&self, wgpu: &Gpu) {
+        self.render_pipeline.write_tone_map(wgpu, &self.tone_map());
+        wgpu.queue.submit([self.render_pipeline.encode_commands(wgpu)]);
+    }
+
+    /// Adds what's been accepted since the counter was last read, if the readback's back. Dispatching does this,
+    /// call it after polling the device to catch up with the last dispatch too.
+    /// The counter wraps, but it's read far more often than it could wrap.
+    pub fn update_accepted_samples(&mut self) {
+        let Some(readback) = &self.samples_readback else {
+            return;
+        };
+        match readback.try_take() {
+            None => return,
+            Some(Ok(bytes)) => {
+                let counter = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                self.accepted_samples += counter.wrapping_sub(self.samples_seen) as u64;
+                self.samples_seen = counter;
+            }
+            Some(Err(e)) => log::warn!("{e:#}"),
+        }
+        self.samples_readback = None;
+    }
+
+    /// Starts the count of accepted samples over from `from`
+    fn reset_accepted_samples(&mut self, wgpu: &Gpu, from: u64) {
+        wgpu.queue.write_buffer(&self.compute_pipeline.accepted_samples_buffer, 0, &0u32.to_le_bytes());
+        self.accepted_samples = from;
+        self.samples_seen = 0;
+        self.samples_readback = None;
+    }
+
+    /// Display settings, scaled to what's been accumulated so far
+    pub fn tone_map(&self) -> ToneMapStruct {
+        let pixels = (self.model.width as u64 * self.model.height as u64).max(1);
+        let iterations_per_pixel = self.iterations() as f32 / pixels as f32;
+        let density_scale = if iterations_per_pixel > 0.0 { 1.0 / (HIT_WEIGHT * iterations_per_pixel) } else { 0.0 };
+        let [r, g, b] = self.model.background_color.map(linear_to_srgb);
+        ToneMapStruct {
+            background: [r, g, b, 1.0],
+            brightness: self.model.brightness as f32,
+            gamma_inv: self.model.gamma_inv as f32,
+            gamma_thresh: self.model.gamma_thresh as f32,
+            vibrancy: self.model.vibrancy as f32,
+            density_scale,
+            ..ToneMapStruct::new()
+        }
+    }
+
+    pub fn dispatch_count(&self) -> i32 {
+        self.dispatch_count
+    }
+
+    /// Iterations run since the histogram was cleared
+    pub fn iterations(&self) -> u64 {
+        self.dispatch_count as u64 * (WORKGROUP_SIZE * KERNEL_WORKGROUP_SIZE) as u64 * self.invocation_iters.max(0) as u64
+    }
+
+    /// log2 of iterations per pixel
+    pub fn iteration_level(&self) -> f64 {
+        let pixels = (self.model.width as u64 * self.model.height as u64).max(1);
+        (self.iterations() as f64 / pixels as f64).log2()
+    }
+
+    /// Samples that made it into the histogram since it was cleared, a dispatch or so behind
+    pub fn accepted_samples(&self) -> u64 {
+        self.accepted_samples
+    }
+
+    /// log2 of accepted samples per pixel, how far along the image really is. -inf before anything lands.
+    pub fn sampling_level(&self) -> f64 {
+        let pixels = (self.model.width as u64 * self.model.height as u64).max(1);
+        (self.accepted_samples as f64 / pixels as f64).log2()
+    }
+
+    /// Paused, or far enough along, so there's nothing to dispatch. Anything that clears the histogram starts it again.
+    pub fn is_stopped(&self) -> bool {
+        self.model.pause_rendering || (self.model.use_stopping_sl && self.sampling_level() >= self.model.stopping_sl as f64)
+    }
+
+    /// The output texture as tightly packed rows of sRGB rgba8. Waits for the GPU.
+    pub fn read_image(&self, wgpu: &Gpu) -> Result<Vec<u8>> {
+        let texture = &self.render_pipeline.texture;
+        let (width, height) = (texture.width(), texture.height());
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Image readback"),
+            size: (padded_row_bytes * height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = wgpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Image readback") });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout { offset: 0, bytes_per_row: Some(padded_row_bytes), rows_per_image: Some(height) },
+            },
+            texture.size(),
+        );
+        wgpu.queue.submit([encoder.finish()]);
+
+        let readback = Readback::start(readback);
+        wgpu.device.poll(Maintain::Wait);
+        let padded = readback.try_take().unwrap_or_else(|| Err(anyhow!("The image never finished copying")))?;
+
+        let mut image = Vec::with_capacity((row_bytes * height) as usize);
+        for row in padded.chunks(padded_row_bytes as usize) {
+            image.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        Ok(image)
+    }
+
+    /// Evenly spaced rows of the accumulated histogram, at most `max_rows` of them, for a look at the image
+    /// without copying all of it. The bytes are `[f32; 4]` per pixel, row after row.
+    pub fn sample_histogram(&self, wgpu: &Gpu, max_rows: u32) -> Readback {
+        let (width, height) = (self.model.width, self.model.height);
+        let row_bytes = width as BufferAddress * size_of::<[f32; 4]>() as BufferAddress;
+        let histogram = &self.compute_pipeline.histogram_buffer;
+        let rows: Vec<u32> = (0..height)
+            .step_by(height.div_ceil(max_rows.max(1)).max(1) as usize)
+            .filter(|y| (*y as BufferAddress + 1) * row_bytes <= histogram.size())
+            .collect();
+        let readback = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Histogram sample readback"),
+            size: (rows.len() as BufferAddress * row_bytes).max(COPY_BUFFER_ALIGNMENT),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = wgpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Histogram sample readback") });
+        for (i, y) in rows.iter().enumerate() {
+            encoder.copy_buffer_to_buffer(histogram, *y as BufferAddress * row_bytes,
+                                          &readback, i as BufferAddress * row_bytes, row_bytes);
+        }
+        wgpu.queue.submit([encoder.finish()]);
+        Readback::start(readback)
+    }
+
+    /// All of the accumulated histogram at the world's size, `[f32; 4]` per pixel, rows top to bottom
+    pub fn read_histogram(&self, wgpu: &Gpu) -> Readback {
+        let histogram = &self.compute_pipeline.histogram_buffer;
+        let size = (self.model.width as BufferAddress * self.model.height as BufferAddress * size_of::<[f32; 4]>() as BufferAddress)
+            .min(histogram.size());
+        let readback = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Histogram readback"),
+            size: size.max(COPY_BUFFER_ALIGNMENT),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = wgpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Histogram readback") });
+        encoder.copy_buffer_to_buffer(histogram, 0, &readback, 0, size);
+        wgpu.queue.submit([encoder.finish()]);
+        Readback::start(readback)
+    }
+
+    /// The histogram followed by the point states, and the rest of what it takes to carry on from them.
+    /// Fill the accumulation with the readback's bytes once they're back.
+    pub fn read_accumulation(&self, wgpu: &Gpu) -> (Readback, Accumulation) {
+        let histogram = &self.compute_pipeline.histogram_buffer;
+        let state = &self.compute_pipeline.state_buffer;
+        let histogram_size = (self.model.width as BufferAddress * self.model.height as BufferAddress * size_of::<[f32; 4]>() as BufferAddress)
+            .min(histogram.size());
+        let readback = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Accumulation readback"),
+            size: histogram_size + state.size(),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = wgpu.device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Accumulation readback") });
+        encoder.copy_buffer_to_buffer(histogram, 0, &readback, 0, histogram_size);
+        encoder.copy_buffer_to_buffer(state, 0, &readback, histogram_size, state.size());
+        wgpu.queue.submit([encoder.finish()]);
+        let acc = Accumulation {
+            world_digest: self.model_digest.unwrap_or_default(),
+            width: self.model.width,
+            height: self.model.height,
+            seed: self.seed,
+            invocation_iters: self.invocation_iters,
+            dispatch_count: self.dispatch_count.max(0) as u64,
+            accepted_samples: self.accepted_samples,
+            ..Accumulation::default()
+        };
+        (Readback::start(readback), acc)
+    }
+
+    /// Puts a saved accumulation back in place of the histogram, to carry on from it.
+    /// It has to be of the model that's loaded, at the same size.
+    pub fn resume(&mut self, wgpu: &Gpu, acc: &Accumulation) -> Result<()> {
+        if self.model_digest.is_none() || self.model_digest != Some(acc.world_digest) {
+            bail!("It was rendered from a different world, or the same one at a different size");
+        }
+        let state = &self.compute_pipeline.state_buffer;
+        if acc.points_state.len() as BufferAddress != state.size() {
+            bail!("It has {} bytes of point states, this build expects {}", acc.points_state.len(), state.size());
+        }
+        let histogram_bytes: &[u8] = bytemuck::cast_slice(&acc.histogram);
+        if (acc.width, acc.height) != (self.model.width, self.model.height)
+            || histogram_bytes.len() as BufferAddress > self.compute_pipeline.histogram_buffer.size() {
+            bail!("It's {}x{}, the world is {}x{}", acc.width, acc.height, self.model.width, self.model.height);
+        }
+        let dispatch_count = i32::try_from(acc.dispatch_count).context("Its dispatch count is too large")?;
+
+        wgpu.queue.write_buffer(&self.compute_pipeline.histogram_buffer, 0, histogram_bytes);
+        wgpu.queue.write_buffer(state, 0, &acc.points_state);
+        self.seed = acc.seed;
+        self.invocation_iters = acc.invocation_iters;
+        self.dispatch_count = dispatch_count;
+        self.reset_accepted_samples(wgpu, acc.accepted_samples);
+        self.write_display_settings(wgpu);
+        Ok(())
+    }
+
+    /// Uploads a new model and clears the histogram, unless only display settings changed.
+    /// Returns the iterators that had to be disabled, and anything else that kept the kernel from building.
+    pub fn update_model(&mut self, wgpu: &Gpu, mut model: IFS) -> Vec<TransformError> {
+        // println!("{:?}", model.camera.create_camera_struct().view_proj_mat);
+        let model_hash = model.get_hash();
+        if self.model_hash == Some(model_hash) {
+            self.model = model;
+            self.write_display_settings(wgpu);
+            return self.errors.clone();
+        }
+
+        if let Some(histogram_buffer) = self.reset_histogram(wgpu, &model) {
+            self.compute_pipeline.histogram_buffer = histogram_buffer;
+            self.compute_pipeline.update_bind_group(wgpu);
+            self.render_pipeline.bind_group = self.compute_pipeline.bind_group.clone();
+        }
+
+        // swaps in the pipelines for this set of transforms, building them if they aren't cached
+        let errors = self.build_iterators(wgpu, &model);
+
+        // clear pstates
+        wgpu.queue.write_buffer(&self.compute_pipeline.state_buffer, 0 as BufferAddress, &vec![0u8; crate::rendering::pipeline_compute::POINTS_STATE_SIZE]);
+
+
+        self.update_settings(wgpu, &mut model);
+
+        // update palette
+        let colors = model.palette.resample(MAX_PALETTE_COLORS);
+        wgpu.queue.write_buffer(&self.compute_pipeline.palette_buffer, 0 as BufferAddress, bytemuck::cast_slice(&colors));
+
+        // update parameters
+        wgpu.queue.write_buffer(&self.compute_pipeline.parameters_buffer, 0 as BufferAddress, bytemuck::cast_slice(&[ParametersStruct {
+            seed: self.seed,
+            width: model.width,
+            height: model.height,
+            dispatch_cnt: 0,
+            reset_points_state: 0,
+            invocation_iters: 0,
+            padding_1: 0,
+            padding_2: 0,
+        }]));
+
+        // resize
+        let texture = &self.render_pipeline.texture;
+        if (texture.width(), texture.height()) != (model.width, model.height) {
+            self.render_pipeline.resize(wgpu, (model.width, model.height));
+        }
+
+        self.dispatch_count = 0;
+        self.reset_accepted_samples(wgpu, 0);
+        self.model = model;
+        self.model_hash = Some(model_hash);
+        self.model_digest = self.model.histogram_digest().ok();
+        self.errors = errors.clone();
+        self.write_display_settings(wgpu);
+        errors
+    }
+
+    /// Everything that changes how the histogram is shown, but not what's in it
+    fn write_display_settings(&self, wgpu: &Gpu) {
+        self.render_pipeline.write_tone_map(wgpu, &self.tone_map());
+        self.render_pipeline.write_response_curves(wgpu, &self.model.response_curves.bake(RESPONSE_CURVE_SIZE));
+    }
+
+    pub fn reset_histogram(&self, wgpu: &Gpu, model: &IFS) -> Option<Buffer>{
+        let hist_size = (model.width * model.height) as usize * size_of::<[f32;4]>();
+        let newhist = vec![0; hist_size];
+        if self.compute_pipeline.histogram_buffer.size() != hist_size as BufferAddress {
+            let new_hist = wgpu.device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("Histogram buffer"),
+                contents: &newhist, // assuming RGBA8?
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            });
+
+            Some(new_hist)
+        } else {
+            wgpu.queue.write_buffer(&self.compute_pipeline.histogram_buffer, 0 as BufferAddress, &newhist);
+            None
+        }
+    }
+
+
+    pub fn create_iterator_struct(i: &crate::model::iterator::Iterator, id: i32) -> IteratorStruct {
+        IteratorStruct {
+            color_speed: 0.0,
+            color_index: 0.0,
+            opacity: 0.0,
+            reset_prob: 0.0,
+            reset_alias: 0,
+            tf_id: id,
+            real_params_index: 0,
+            vec3_params_index: 0,
+            shading_mode: 0,
+            tf_mix: 0.0,
+            tf_add: 0.0,
+            padding2: 0,
+        }
+    }
+    //TODO NIGHTMARE NIGHTMARE NIGHTMARE NIGHTMARE
+    fn build_iterators(&mut self, wgpu: &Gpu, model: &IFS) -> Vec<TransformError> {
+        let src = KERNEL_SRC;
+
+        let mut src_string = String::new();
+        let mut transforms: Vec<&Transform> = vec![]; //one branch per distinct transform, shared by its iterators
+        let mut iterators: Vec<IteratorStruct> = vec![];
+        let mut real_params: Vec<[f32; 4]> = vec![]; //16 byte stride, see RealParam
+        let mut vec3_params: Vec<[f32; 4]> = vec![];
+        let mut errors = vec![];
+
+        for it in &model.iterators {
+            let tf = &it.transform;
+            let real_names = tf.real_param_names();
+            let vec3_names = tf.vec3_param_names();
+            let checked = self.validate(src, tf).and_then(|_| {
+                if real_params.len() + real_names.len() > MAX_PARAMS || vec3_params.len() + vec3_names.len() > MAX_PARAMS {
+                    return Err(TransformError {
+                        iterator_id: None,
+                        transform: format!("{} {}", tf.name, tf.version),
+                        line: None,
+                        message: format!("Out of room for params, the limit is {MAX_PARAMS} of each kind"),
+                    });
+                }
+                Ok(())
+            });
+            if let Err(e) = checked {
+                // no branch for it, so it acts as the identity, and keep it from drawing
+                log::warn!("Disabling iterator {}: {e}", it.id);
+                errors.push(TransformError { iterator_id: Some(it.id), ..e });
+                iterators.push(Self::create_iterator_struct(it, -1)); //opacity 0, and no branch has tf_id -1
+                continue;
+            }
+
+            let tf_id = match transforms.iter().position(|t| *t == tf && t.source_code == tf.source_code) {
+                Some(i) => i,
+                None => {
+                    transforms.push(tf);
+                    src_string.push_str(&transform_branch(transforms.len() - 1, tf).0);
+                    transforms.len() - 1
+                }
+            };
+
+            let real_params_index = real_params.len() as i32;
+            let vec3_params_index = vec3_params.len() as i32;
+            for name in real_names {
+                let val = it.real_params.get(name).unwrap_or(&tf.real_params[name]);
+                real_params.push([*val, 0.0, 0.0, 0.0]);
+            }
+            for name in vec3_names {
+                let [x, y, z] = it.vec3_params.get(name).unwrap_or(&tf.vec3_params[name]);
+                vec3_params.push([*x, *y, *z, 0.0]);
+            }
+
+            // Todo: never, lol
+            iterators.push(IteratorStruct {
+                color_speed: it.color_speed,
+                color_index: it.color_index,
+                opacity: it.opacity,
+                reset_prob: 0.0,
+                reset_alias: 0,
+                tf_id: tf_id as i32,
+                real_params_index,
+                vec3_params_index,
+                shading_mode: it.shading_mode,
+                tf_mix: it.mix,
+                tf_add: it.add,
+                padding2: 0,
+            });
+        }
+
+        // where points start, and where they go next
+        for (it, entry) in iterators.iter_mut().zip(start_alias_table(model)) {
+            it.reset_prob = entry.prob;
+            it.reset_alias = entry.alias;
+        }
+        let alias_tables: Vec<[f32; 4]> = xaos_alias_tables(model).into_iter()
+            .map(|entry| [entry.prob, entry.alias as f32, 0.0, 0.0])
+            .collect();
+
+        wgpu.queue.write_buffer(&self.compute_pipeline.iterators_buffer, 0 as BufferAddress, bytemuck::cast_slice(&iterators));
+        if !alias_tables.is_empty() {
+            wgpu.queue.write_buffer(&self.compute_pipeline.alias_tables_buffer, 0 as BufferAddress, bytemuck::cast_slice(&alias_tables));
+        }
+        if !real_params.is_empty() {
+            wgpu.queue.write_buffer(&self.compute_pipeline.real_params_buffer, 0 as BufferAddress, bytemuck::cast_slice(&real_params));
+        }
+        if !vec3_params.is_empty() {
+            wgpu.queue.write_buffer(&self.compute_pipeline.vec3_params_buffer, 0 as BufferAddress, bytemuck::cast_slice(&vec3_params));
+        }
+
+        let key = kernel_key(&transforms);
+        if self.kernel_key != Some(key) {
+            match self.kernels.get(&key).cloned() {
+                Some(kernel) => self.use_kernel(key, kernel),
+                None => match self.compile_kernel(wgpu, &src.replace(TRANSFORMS_MARKER, &src_string)) {
+                    Ok(kernel) => {
+                        self.kernels.insert(key, kernel.clone());
+                        self.use_kernel(key, kernel);
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        errors
+    }
+
+    fn use_kernel(&mut self, key: u64, kernel: CompiledKernel) {
+        self.compute_pipeline.compute_pipeline = kernel.compute;
+        self.render_pipeline.pipeline = kernel.render;
+        self.kernel_key = Some(key);
+    }
+
+    fn compile_kernel(&mut self, wgpu: &Gpu, src: &str) -> Result<CompiledKernel, TransformError> {
+        // transforms that pass on their own should pass together, but if not, keep the old kernel rather than panic
+        wgpu.device.push_error_scope(ErrorFilter::Validation);
+        let shader = wgpu.device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(src)),
+        });
+        let kernel = CompiledKernel {
+            compute: Arc::new(Compute::create_pipeline_with(wgpu, &self.compute_pipeline.pipeline_layout, &shader)),
+            render: Arc::new(Render::create_pipeline_with(wgpu, &self.render_pipeline.pipeline_layout, &shader)),
+        };
+        match futures::executor::block_on(wgpu.device.pop_error_scope()) {
+            None => {
+                self.shader = shader;
+                Ok(kernel)
+            }
+            Some(e) => Err(TransformError {
+                iterator_id: None,
+                transform: String::new(),
+                line: None,
+                message: format!("The kernel failed to build, keeping the previous one: {e}"),
+            }),
+        }
+    }
+
+    fn validate(&mut self, kernel: &str, tf: &Transform) -> Result<(), TransformError> {
+        // param values can't break a transform, only their names can
+        let mut hasher = DefaultHasher::new();
+        kernel.hash(&mut hasher);
+        tf.source_code.hash(&mut hasher);
+        let mut names: Vec<&String> = tf.real_params.keys().chain(tf.vec3_params.keys()).collect();
+        names.sort();
+        names.hash(&mut hasher);
+        tf.name.hash(&mut hasher);
+        tf.version.hash(&mut hasher);
+        tf.source_line.hash(&mut hasher);
+        self.validated.entry(hasher.finish())
+            .or_insert_with(|| validate_transform(kernel, tf))
+            .clone()
+    }
+
+    fn update_settings(&self, wgpu: &Gpu, model: &mut IFS) {
+        let settings = SettingsStruct {
+            camera_params: model.camera.create_camera_struct(),
+            fog_effect: model.fog_effect,
+            itnum: model.iterators.len() as u32,
+            palettecnt: MAX_PALETTE_COLORS as i32,
+            mark_area_in_focus: 1,
+            warmup: model.fuse,
+            entropy: model.entropy,
+            max_filter_radius: 0,
+            padding0: 0,
+            filter_method: 0,
+            filter_param0: 0.0,
+            filter_param1: 0.0,
+            filter_param2: 0.0
+        };
+
+        wgpu.queue.write_buffer(
+            &self.compute_pipeline.settings_buffer,
+            0 as BufferAddress,
+            bytemuck::bytes_of(&settings)
+        );
+
+    }
+}
@@ -142,3 +142,51 @@ impl <'a> Bufferable<'a> for SettingsStruct {
         }
     }
 }
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct ToneMapStruct {
+    pub background: [f32; 4], //sRGB, what it should look like on screen
+    pub brightness: f32,
+    pub gamma_inv: f32,
+    pub gamma_thresh: f32,
+    pub vibrancy: f32,
+
+    pub density_scale: f32, //turns accumulated alpha into hits per iteration per pixel
+    pub padding0: f32,
+    pub padding1: f32,
+    pub padding2: f32,
+}
+
+impl ToneMapStruct {
+    pub fn new() -> Self {
+        Self {
+            background: [0.0, 0.0, 0.0, 1.0],
+            brightness: 1.0,
+            gamma_inv: 1.0,
+            gamma_thresh: 0.0,
+            vibrancy: 1.0,
+            density_scale: 0.0,
+            padding0: 0.0,
+            padding1: 0.0,
+            padding2: 0.0,
+        }
+    }
+}
+
+impl Default for ToneMapStruct {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl <'a> Bufferable<'a> for ToneMapStruct {
+    fn desc() -> BufferDescriptor<'a> {
+        BufferDescriptor {
+            label: None,
+            size: std::mem::size_of::<ToneMapStruct>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::borrow::Cow;
-use std::fs;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::iter::Iterator;
 use std::mem::size_of;
 use std::num::NonZeroU32;
-use std::path::Iter;
+use std::path::{Iter, PathBuf};
+use std::sync::Arc;
 use std::sync::mpsc::{Receiver, SyncSender, TryRecvError};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use egui::TextureId;
 use egui_wgpu::RenderState;
 use futures::stream::iter;
@@ -19,21 +22,74 @@ use crate::model::transform::Transform;
 use crate::rendering::gpu_structs::*;
 use crate::rendering::pipeline_compute::*;
 use crate::rendering::pipeline_render::Render;
+use crate::rendering::gpu_renderer::{Gpu, GpuRenderer, Readback};
+use crate::rendering::accumulation::Accumulation;
+use crate::rendering::image_export::{save_image, tone_map_image, ImageFormat};
+use crate::rendering::tonal_histogram::TonalHistogram;
+use crate::rendering::transform_validation::*;
 
 
 pub struct GraphicsEngine {
-    pub compute_pipeline: Compute,
-    pub render_pipeline: Render,
-    pub shader: ShaderModule,
+    pub renderer: GpuRenderer,
+    gpu: Gpu,
 
     work_status_tx: SyncSender<()>,
     ifs_rx: Receiver<IFS>,
     app_tx: SyncSender<TextureId>,
-    dispatch_count: i32,
-    model: IFS,
+    errors_tx: SyncSender<Vec<TransformError>>,
+    tonal_tx: SyncSender<TonalHistogram>,
+    tonal_readback: Option<(Readback, ToneMapStruct, Vec<[f32; 4]>)>, //with the settings to tone map it with
+    last_tonal_readback: Instant,
+    requests_rx: Receiver<EngineRequest>,
+    saves: Vec<PendingSave>,
+    stats_tx: SyncSender<RenderStats>,
+    rate_since: (Instant, u64), //when iterations per second were last measured, and the iterations then
+    iterations_per_second: f64,
+    needs_redraw: bool, //the histogram or how it's shown changed while stopped
     // pub(crate) output_texture: TextureId
 }
 
+/// The engine's ends of its channels to and from the UI
+pub struct EngineChannels {
+    pub ifs_rx: Receiver<IFS>,
+    pub app_tx: SyncSender<TextureId>,
+    pub errors_tx: SyncSender<Vec<TransformError>>,
+    pub tonal_tx: SyncSender<TonalHistogram>,
+    pub requests_rx: Receiver<EngineRequest>,
+    pub stats_tx: SyncSender<RenderStats>,
+}
+
+/// How far along the render is
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub sampling_level: f64, //log2 of accepted samples per pixel, -inf before any
+    pub iterations_per_second: f64,
+    pub stopped: bool, //paused, or reached stopping_sl
+}
+
+/// What the UI can ask of the engine besides a new model. Each says how it went on `done`.
+pub enum EngineRequest {
+    /// At the world's size, from the float histogram
+    SaveImage { path: PathBuf, format: ImageFormat, alpha: bool, done: SyncSender<anyhow::Result<String>> },
+    /// The raw histogram and point states, to resume from later
+    SaveAccumulation { path: PathBuf, done: SyncSender<anyhow::Result<String>> },
+    /// Carries on from a saved accumulation of the current world
+    Resume { accumulation: Box<Accumulation>, done: SyncSender<anyhow::Result<String>> },
+}
+
+/// A readback on its way to a file, with what it takes to write it
+struct PendingSave {
+    readback: Readback,
+    path: PathBuf,
+    kind: PendingKind,
+    done: SyncSender<anyhow::Result<String>>,
+}
+
+enum PendingKind {
+    Image { format: ImageFormat, alpha: bool, tone_map: ToneMapStruct, curves: Vec<[f32; 4]>, size: (u32, u32) },
+    Accumulation(Accumulation), //everything but the buffers, which come with the readback
+}
+
 // create texture view, create shader, create render_pipeline,
 // per frame, create encoder, create render pass (can this be reused?), and submit to queue
 
@@ -42,44 +98,65 @@ pub const HISTOGRAM_WIDTH: usize = 1920;
 pub const HISTOGRAM_HEIGHT: usize = 1080;
 pub const WORKGROUP_SIZE: usize = 256;
 pub const MAX_ITERATORS : usize =	100;
-pub const MAX_PARAMS : usize = (2 * MAX_ITERATORS);
+pub const MAX_PARAMS : usize = (8 * MAX_ITERATORS);
 pub const MAX_PALETTE_COLORS : usize = 256;
+pub const RESPONSE_CURVE_SIZE: usize = 256; //entries in the lookup table the render pass applies the curves with
 pub const MAX_XAOS : usize = (MAX_ITERATORS * MAX_ITERATORS);
+pub const KERNEL_WORKGROUP_SIZE: usize = 64; //@workgroup_size of main in ifs_kernel.wgsl
+pub const DEFAULT_SEED: u32 = 699912576;
+pub const HIT_WEIGHT: f32 = 0.0005; //alpha the kernel adds to a pixel per hit
+pub const TONAL_HISTOGRAM_INTERVAL: Duration = Duration::from_millis(500);
+pub const TONAL_HISTOGRAM_ROWS: u32 = 128; //rows of the image the tonal histogram is taken from
+pub const ITERATION_RATE_INTERVAL: Duration = Duration::from_secs(1); //iterations per second are averaged over this
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 struct Color([f32; 4]);
 
 impl GraphicsEngine {
-    pub fn new_engine(wgpu: &RenderState, work_status_tx: SyncSender<()>, ifs_rx: Receiver<IFS>, app_tx: SyncSender<TextureId>) -> Self {
-        let shader_desc = wgpu::include_wgsl!("ifs_kernel.wgsl");
-        let shader = wgpu.device.create_shader_module(shader_desc);
-
-        let compute = Compute::init(wgpu, &shader);
-
-        // TODO: unfuck that lol
-        let render = Render::init(wgpu, &shader, compute.bind_group_layout.clone(), compute.bind_group.clone(), (1920, 1080));
+    pub fn new_engine(wgpu: &RenderState, work_status_tx: SyncSender<()>, channels: EngineChannels) -> Self {
+        let EngineChannels { ifs_rx, app_tx, errors_tx, tonal_tx, requests_rx, stats_tx } = channels;
+        let gpu = Gpu::from(wgpu);
+        let renderer = GpuRenderer::new(&gpu);
 
-        let tex_id = wgpu.renderer.write().register_native_texture(&*wgpu.device, &render.texture_view, FilterMode::Nearest);
+        let tex_id = wgpu.renderer.write().register_native_texture(&wgpu.device, &renderer.render_pipeline.texture_view, FilterMode::Nearest);
+        let _ = app_tx.try_send(tex_id); //the first model only resizes if it has to
 
         Self {
-            compute_pipeline: compute,
-            render_pipeline: render,
-            shader,
+            renderer,
+            gpu,
             work_status_tx,
             ifs_rx,
             app_tx,
-            dispatch_count: 0,
-            model: Default::default(),
+            errors_tx,
+            tonal_tx,
+            tonal_readback: None,
+            last_tonal_readback: Instant::now(),
+            requests_rx,
+            saves: vec![],
+            stats_tx,
+            rate_since: (Instant::now(), 0),
+            iterations_per_second: 0.0,
+            needs_redraw: false,
         }
     }
 
     pub fn render(&mut self, wgpu: &RenderState) {
         match self.ifs_rx.try_recv() {
-            Ok(mut model) => {
+            Ok(model) => {
                 println!("updating model");
-                self.update_model(wgpu, &mut model);
-                self.model = model;
+                let texture = &self.renderer.render_pipeline.texture;
+                let size = (texture.width(), texture.height());
+                let errors = self.renderer.update_model(&self.gpu, model);
+                let _ = self.errors_tx.try_send(errors);
+
+                // a resized texture has to be shown in place of the old one
+                let texture = &self.renderer.render_pipeline.texture;
+                if (texture.width(), texture.height()) != size {
+                    let tex_id = wgpu.renderer.write().register_native_texture(&wgpu.device, &self.renderer.render_pipeline.texture_view, FilterMode::Nearest);
+                    let _ = self.app_tx.try_send(tex_id);
+                }
+                self.needs_redraw = true;
             }
             Err(e) => {}
         }
@@ -87,198 +164,127 @@ impl GraphicsEngine {
         // println!("dispatch: {}", self.dispatch_count);
         //
 
-        wgpu.queue.write_buffer(&self.compute_pipeline.parameters_buffer, 0 as BufferAddress, bytemuck::cast_slice(&[ParametersStruct {
-            seed: 699912576,
-            width: self.model.width,
-            height: self.model.height,
-            dispatch_cnt: self.dispatch_count,
-            reset_points_state: 0, // TODO: ??????
-            invocation_iters: 512,
-            padding_1: 0,
-            padding_2: 0,
-        }]));
-
-        self.dispatch_count += 1;
-
-        let compute_cmd = self.compute_pipeline.encode_commands(wgpu);
-        let render_cmd = self.render_pipeline.encode_commands(wgpu);
+        let stopped = self.renderer.is_stopped();
+        if !stopped {
+            self.renderer.dispatch(&self.gpu);
+        } else if self.needs_redraw {
+            self.renderer.redraw(&self.gpu);
+        }
+        self.needs_redraw = false;
+        self.update_tonal_histogram();
+        self.update_requests();
+        self.update_stats(stopped);
 
         // let moved_tx = self.work_status_tx.clone();
         // moved_tx.send(()).unwrap();
         // wgpu.queue.on_submitted_work_done(move || moved_tx.send(()).unwrap());
-        wgpu.queue.submit([compute_cmd, render_cmd]);
         sleep(Duration::from_millis(16));
         // TODO: determine sleep time
         self.work_status_tx.send(()).unwrap();
     }
 
-    fn update_model(&mut self, wgpu: &RenderState, model: &mut IFS) {
-        // println!("{:?}", model.camera.create_camera_struct().view_proj_mat);
-
-        if let Some(histogram_buffer) = self.reset_histogram(wgpu, model) {
-            self.compute_pipeline.histogram_buffer = histogram_buffer;
-            self.compute_pipeline.update_bind_group(wgpu);
-            self.render_pipeline.bind_group = self.compute_pipeline.bind_group.clone();
-        }
-
-        // building the iterators creates a new shader
-        self.build_iterators(wgpu, model);
-
-        self.compute_pipeline.recreate_pipeline_with_shader(wgpu, &self.shader);
-        self.render_pipeline.recreate_pipeline_with_shader(wgpu, &self.shader);
-
-        // clear pstates
-        wgpu.queue.write_buffer(&self.compute_pipeline.state_buffer, 0 as BufferAddress, &vec![0u8; size_of::<f32>() * 8 * crate::rendering::pipeline_compute::WORKGROUP_SIZE]);
-
-
-        self.update_settings(wgpu, model);
-
-        // update palette
-        // let color = Color([0.0, 0.0001, 0.4, 1.0]);
-        // let colors = vec![color; MAX_PALETTE_COLORS];
-        // let colors = vec![]
-        let mut colors = vec![];
-        for i in 0..MAX_PALETTE_COLORS {
-            let c = 1.0 - 1.0/MAX_PALETTE_COLORS as f32;
-            colors.push([c, c, c, 1.0]);
+    fn update_stats(&mut self, stopped: bool) {
+        let iterations = self.renderer.iterations();
+        let (since, iterations_then) = self.rate_since;
+        if stopped || iterations < iterations_then {
+            self.iterations_per_second = 0.0;
+            self.rate_since = (Instant::now(), iterations);
+        } else if since.elapsed() >= ITERATION_RATE_INTERVAL {
+            self.iterations_per_second = (iterations - iterations_then) as f64 / since.elapsed().as_secs_f64();
+            self.rate_since = (Instant::now(), iterations);
         }
-
-        wgpu.queue.write_buffer(&self.compute_pipeline.palette_buffer, 0 as BufferAddress, &bytemuck::cast_slice(&colors));
-
-        // update parameters
-        wgpu.queue.write_buffer(&self.compute_pipeline.parameters_buffer, 0 as BufferAddress, bytemuck::cast_slice(&[ParametersStruct {
-            seed: 699912576,
-            width: model.width,
-            height: model.height,
-            dispatch_cnt: 0,
-            reset_points_state: 0,
-            invocation_iters: 0,
-            padding_1: 0,
-            padding_2: 0,
-        }]));
-
-        // resize
-        self.render_pipeline.resize(wgpu, (model.width, model.height));
-        let tex_id = wgpu.renderer.write().register_native_texture(&*wgpu.device, &self.render_pipeline.texture_view, FilterMode::Nearest);
-        let _ = self.app_tx.try_send(tex_id).unwrap();
+        let _ = self.stats_tx.try_send(RenderStats {
+            sampling_level: self.renderer.sampling_level(),
+            iterations_per_second: self.iterations_per_second,
+            stopped,
+        });
     }
 
-    pub fn reset_histogram(&self, wgpu: &RenderState, model: &IFS) -> Option<Buffer>{
-        // TODO: if has resize
-        let hist_size = (model.width * model.height) as usize * size_of::<[f32;4]>();
-        let newhist = vec![0; hist_size];
-        if true {
-            let new_hist = wgpu.device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("Histogram buffer"),
-                contents: &newhist, // assuming RGBA8?
-                usage: BufferUsages::STORAGE,
-            });
-
-            return Some(new_hist);
-        } else {
-            wgpu.queue.write_buffer(&self.compute_pipeline.histogram_buffer, 0 as BufferAddress, &newhist);
-            return None;
+    /// Starts reading back what each save asked for, and hands the ones that have arrived to a thread
+    /// of their own to write, so neither this nor the UI waits on them.
+    fn update_requests(&mut self) {
+        while let Ok(request) = self.requests_rx.try_recv() {
+            match request {
+                EngineRequest::SaveImage { path, format, alpha, done } => {
+                    let model = self.renderer.model();
+                    self.saves.push(PendingSave {
+                        readback: self.renderer.read_histogram(&self.gpu),
+                        path,
+                        kind: PendingKind::Image {
+                            format,
+                            alpha,
+                            tone_map: self.renderer.tone_map(),
+                            curves: model.response_curves.bake(RESPONSE_CURVE_SIZE),
+                            size: (model.width, model.height),
+                        },
+                        done,
+                    });
+                }
+                EngineRequest::SaveAccumulation { path, done } => {
+                    let (readback, acc) = self.renderer.read_accumulation(&self.gpu);
+                    self.saves.push(PendingSave { readback, path, kind: PendingKind::Accumulation(acc), done });
+                }
+                EngineRequest::Resume { accumulation, done } => {
+                    let resumed = self.renderer.resume(&self.gpu, &accumulation)
+                        .map(|_| format!("Resumed at sampling level {:.2}", self.renderer.sampling_level()));
+                    self.needs_redraw = true;
+                    let _ = done.send(resumed);
+                }
+            }
         }
-    }
-
-
-    pub fn create_iterator_struct(i: &crate::model::iterator::Iterator, id: i32) -> IteratorStruct {
-        IteratorStruct {
-            color_speed: 0.0,
-            color_index: 0.0,
-            opacity: 0.0,
-            reset_prob: 0.0,
-            reset_alias: 0,
-            tf_id: id,
-            real_params_index: 0,
-            vec3_params_index: 0,
-            shading_mode: 0,
-            tf_mix: 0.0,
-            tf_add: 0.0,
-            padding2: 0,
+        if self.saves.is_empty() {
+            return;
         }
-    }
-    //TODO NIGHTMARE NIGHTMARE NIGHTMARE NIGHTMARE
-    fn build_iterators(&mut self, wgpu: &RenderState, model: &IFS) {
-        let mut src_string = String::new();
-        let mut iterators: Vec<IteratorStruct> = vec![];
-
-        for (n, it) in model.iterators.iter().enumerate() {
-            let mut real_vars = String::new();
-            for (name, val) in &it.real_params {
-                real_vars.push_str(&format!("\nvar {name} = {val};"))
-            }
-
-            let mut vec3_vars = String::new();
-            for (name, val) in &it.vec3_params {
-                vec3_vars.push_str(&format!("\nvar {name} = vec3({}, {}, {});", val[0], val[1], val[2]))
-            }
-
-            src_string.push_str(&format!("
-                if (iter.tf_id == {n}) {{
-                    {real_vars}
-                    {vec3_vars}
-                    {}
-                }}", it.transform.source_code)
-            );
-
-            // Todo: never, lol
-            iterators.push(IteratorStruct {
-                color_speed: it.color_speed,
-                color_index: it.color_index,
-                opacity: it.opacity,
-                reset_prob: 0.0,
-                reset_alias: 0,
-                tf_id: n as i32,
-                real_params_index: 0, //MAKE ME REAAAAAAAAAL
-                vec3_params_index: 0, // VEC ME DADDY
-                shading_mode: 0,
-                tf_mix: it.mix,
-                tf_add: it.add,
-                padding2: 0,
+        self.gpu.device.poll(Maintain::Poll);
+        let mut pending = vec![];
+        for save in self.saves.drain(..) {
+            let Some(bytes) = save.readback.try_take() else {
+                pending.push(save);
+                continue;
+            };
+            std::thread::spawn(move || {
+                let PendingSave { path, kind, done, .. } = save;
+                let result = bytes.and_then(|bytes| match kind {
+                    PendingKind::Image { format, alpha, tone_map, curves, size: (width, height) } => {
+                        let histogram: Vec<[f32; 4]> = bytes.chunks_exact(size_of::<[f32; 4]>())
+                            .map(bytemuck::pod_read_unaligned)
+                            .collect();
+                        let pixels = tone_map_image(&histogram, &tone_map, &curves, format, alpha);
+                        save_image(&path, format, width, height, &pixels, alpha)
+                    }
+                    PendingKind::Accumulation(mut acc) => {
+                        acc.fill(&bytes)?;
+                        acc.save(&path)
+                    }
+                });
+                let _ = done.send(result.map(|_| format!("Saved {}", path.display())));
             });
         }
-
-        // TODO: DO NOT REBUILD THE SHADER THIS MUCH
-        // EMBRACE NESTED RFLECTION GARBAGE
-        let src = fs::read_to_string("src/rendering/ifs_kernel.wgsl").unwrap();
-
-        // let src = include_str!("ifs_kernel.wgsl").to_owned();
-        let modified_src = src.replace("@transforms", &src_string);
-
-        let shader_desc = ShaderModuleDescriptor {
-            label: None,
-            source: ShaderSource::Wgsl(Cow::Owned(modified_src)),
-        };
-        let shader = wgpu.device.create_shader_module(shader_desc);
-
-        self.shader = shader;
-
-        // write iterators buffer
-        wgpu.queue.write_buffer(&self.compute_pipeline.iterators_buffer, 0 as BufferAddress, &bytemuck::cast_slice(&iterators));
+        self.saves = pending;
     }
-    fn update_settings(&self, wgpu: &RenderState, model: &mut IFS) {
-        let settings = SettingsStruct {
-            camera_params: model.camera.create_camera_struct(),
-            fog_effect: 0.0,
-            itnum: model.iterators.len() as u32,
-            palettecnt: MAX_PALETTE_COLORS as i32,
-            mark_area_in_focus: 1,
-            warmup: model.fuse,
-            entropy: model.entropy as f32,
-            max_filter_radius: 0,
-            padding0: 0,
-            filter_method: 0,
-            filter_param0: 0.0,
-            filter_param1: 0.0,
-            filter_param2: 0.0
-        };
-
-        wgpu.queue.write_buffer(
-            &self.compute_pipeline.settings_buffer,
-            0 as BufferAddress,
-            bytemuck::bytes_of(&settings)
-        );
 
+    /// Every so often, sends the UI a tonal histogram of a sample of the image. Doesn't wait for the GPU,
+    /// a readback that isn't back yet is checked on again next frame.
+    fn update_tonal_histogram(&mut self) {
+        self.gpu.device.poll(Maintain::Poll);
+        if let Some((readback, tone_map, curves)) = &self.tonal_readback {
+            match readback.try_take() {
+                None => return,
+                Some(Ok(bytes)) => {
+                    let samples: Vec<[f32; 4]> = bytes.chunks_exact(size_of::<[f32; 4]>())
+                        .map(bytemuck::pod_read_unaligned)
+                        .collect();
+                    let _ = self.tonal_tx.try_send(TonalHistogram::from_samples(&samples, tone_map, curves));
+                }
+                Some(Err(e)) => log::warn!("{e:#}"),
+            }
+            self.tonal_readback = None;
+        }
+        if self.last_tonal_readback.elapsed() >= TONAL_HISTOGRAM_INTERVAL {
+            let readback = self.renderer.sample_histogram(&self.gpu, TONAL_HISTOGRAM_ROWS);
+            let curves = self.renderer.model().response_curves.bake(RESPONSE_CURVE_SIZE);
+            self.tonal_readback = Some((readback, self.renderer.tone_map(), curves));
+            self.last_tonal_readback = Instant::now();
+        }
     }
 }
@@ -4,7 +4,7 @@ const DEGTORAD: f32 = 0.0174532925f;
 
 const MAX_ITERATORS : u32 =	100;
 const MAX_PALETTE_COLORS : u32 = 256;
-const MAX_PARAMS : u32 = (2 * MAX_ITERATORS);
+const MAX_PARAMS : u32 = (8 * MAX_ITERATORS);
 const MAX_XAOS : u32 = (MAX_ITERATORS * MAX_ITERATORS);
 
 struct camera_params
@@ -89,19 +89,21 @@ struct RealParam {
 
 @group(0) @binding(3) var<uniform> iterators: array<Iterator, MAX_ITERATORS>;  // filled from cpu
 
-@group(0) @binding(4) var<uniform> alias_tables: array<vec4<f32>, MAX_ITERATORS>; // filled from cpu
+@group(0) @binding(4) var<storage, read> alias_tables: array<vec4<f32>>; // filled from cpu, itnum x itnum of (prob, alias, 0, 0)
 
 @group(0) @binding(5) var<uniform> palette: array<vec4<f32>, MAX_PALETTE_COLORS>; // filled from cpu
 
-//@group(0) @binding(6) var<uniform> real_params: array<RealParam, MAX_PARAMS>; // filled from cpu
+@group(0) @binding(6) var<uniform> real_params: array<RealParam, MAX_PARAMS>; // filled from cpu
 
-//@group(0) @binding(7) var<uniform> vec3_params: array<vec4<f32>, MAX_PARAMS>; // filled from cpu
+@group(0) @binding(7) var<uniform> vec3_params: array<vec4<f32>, MAX_PARAMS>; // filled from cpu
 
 @group(0) @binding(8) var<uniform> parameters: Parameters; // filled from cpu
 
 @group(0) @binding(9) var<storage, read_write> next_sample: u32;
 
-//@group(0) @binding(10) var<storage, write> done: u32;
+@group(0) @binding(10) var<storage, read_write> accepted_samples: atomic<u32>; // hits that reached the histogram, wraps around
+
+//@group(0) @binding(11) var<storage, write> done: u32;
 
 fn f32_inf_or_nan(f: f32) -> bool { //dumb func to check inf/nan
     let bits = bitcast<u32>(f);
@@ -485,6 +487,7 @@ fn main(
 	var p : P_State;
 	if parameters.reset_points_state == 1 { p = reset_state(); }
 	else { p = state[gid]; }
+	var accepted = 0u; //added to accepted_samples once at the end, rather than an atomic per hit
 
 	for (var i = 0; i < parameters.invocation_iters; i++)
 	{
@@ -519,7 +522,7 @@ fn main(
             continue;
         }
 
-		apply_coloring(selected_iterator, p0_pos, p.pos, p.color_index);
+		p.color_index = apply_coloring(selected_iterator, p0_pos, p.pos, p.color_index);
 		p.iteration_depth++;
 
 		if (p.iteration_depth < i32(settings.warmup) || selected_iterator.opacity == 0.0) {
@@ -581,15 +584,18 @@ fn main(
             }
             if (nb.x >= 0 && nb.x < i32(parameters.width) && nb.y >= 0 && nb.y < i32(parameters.height)) {
                 accumulate_hit(nb, aw * color);
+                accepted++;
                 //accumulate_hit(nb, vec4(0.0, 1.0, 0.0, 1.0));
             }
 		} else {
 			accumulate_hit(proj, color);
+			accepted++;
 			//accumulate_hit(proj, vec4(1.0, 0.0, 0.0, 2.0));
 		}
 //        done = u32(1);
 	}
 	state[gid] = p;
+	atomicAdd(&accepted_samples, accepted);
 }
 
 // .....................................................
@@ -618,13 +624,73 @@ fn vs_main(
 }
 
 
+// tone mapping, log density as in flam3
+
+struct ToneMap {
+    background: vec4<f32>, //sRGB
+    brightness: f32,
+    gamma_inv: f32,
+    gamma_thresh: f32,
+    vibrancy: f32,
+
+    density_scale: f32,
+    padding0: f32,
+    padding1: f32,
+    padding2: f32,
+}
+
+@group(1) @binding(0) var<uniform> tone_map: ToneMap; // filled from cpu, every dispatch
+@group(1) @binding(1) var<storage, read> response_curves: array<vec4<f32>>; // rgba, baked from the world's curves
+
+const LN10: f32 = 2.30258509299f;
+
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let lo = c / 12.92;
+    let hi = pow((c + 0.055) / 1.055, vec3(2.4));
+    return select(hi, lo, c <= vec3(0.04045));
+}
+
+//the curve for one channel at x, linear between the table's entries
+fn response_curve(x: f32, channel: u32) -> f32 {
+    let last = arrayLength(&response_curves) - 1u;
+    let pos = clamp(x, 0.0, 1.0) * f32(last);
+    let i = min(u32(pos), last);
+    let j = min(i + 1u, last);
+    return mix(response_curves[i][channel], response_curves[j][channel], pos - f32(i));
+}
+
 @fragment
 fn fs_main(@builtin(position) coord_in: vec4<f32>) -> @location(0) vec4<f32> {
-    // TODO: render from a texture
-    var idx = coord_in.x;
-    idx += coord_in.y * f32(parameters.width);
+    let px = vec2<u32>(floor(coord_in.xy));
+    let acc = histogram[px.x + px.y * parameters.width];
+    let bg = tone_map.background.rgb;
+    var color = vec3(0.0);
+    var a = 0.0;
+    if (acc.w > 0.0) {
+        //log of the density scales the pixel, so sparse areas still show up next to dense ones
+        let ls = tone_map.brightness * log(1.0 + acc.w * tone_map.density_scale) / (LN10 * acc.w);
+        let alpha = acc.w * ls;
+        let rgb = acc.rgb * ls;
+
+        //gamma, with a linear ramp below the threshold so dark noise isn't blown up
+        let g = tone_map.gamma_inv;
+        var funcval = pow(alpha, g);
+        if (alpha < tone_map.gamma_thresh) {
+            let frac = alpha / tone_map.gamma_thresh;
+            funcval = (1.0 - frac) * alpha * pow(tone_map.gamma_thresh, g) / tone_map.gamma_thresh + frac * funcval;
+        }
+
+        //vibrancy 1 applies gamma to the density and keeps hues, 0 applies it to each channel
+        let vib = tone_map.vibrancy;
+        color = vib * funcval / alpha * rgb + (1.0 - vib) * pow(max(rgb, vec3(0.0)), vec3(g));
+        a = clamp(funcval, 0.0, 1.0);
+    }
 
-//    let bg = vec4(0.0, 0.0, settings.camera.depth_of_field * 0.5, 0.0);
+    //response curves, alpha decides how much background shows through, then the color curves go over the result
+    a = response_curve(a, 3u);
+    color = clamp(color + (1.0 - a) * bg, vec3(0.0), vec3(1.0));
+    color = vec3(response_curve(color.r, 0u), response_curve(color.g, 1u), response_curve(color.b, 2u));
 
-    return histogram[parameters.width /2  + u32(floor(idx))];
+    //the output texture is sRGB, so this is what ends up in it
+    return vec4(srgb_to_linear(color), 1.0);
 }
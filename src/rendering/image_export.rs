@@ -0,0 +1,109 @@
+//! Saving the image at the world's size. It's tone mapped from the float histogram on the CPU,
+//! so formats with more than 8 bits get more than 8 bits of image.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, ImageBuffer, Rgb, Rgb32FImage, RgbImage, Rgba, Rgba32FImage, RgbaImage};
+use strum_macros::EnumIter;
+use crate::rendering::gpu_structs::ToneMapStruct;
+use crate::rendering::tone_map::{apply_color_curves, apply_color_curves_unclamped, tone_map_pixel, tone_map_pixel_unclamped};
+use crate::util::math_extensions::srgb_to_linear;
+
+#[derive(Clone, Copy, Debug, PartialEq, EnumIter)]
+pub enum ImageFormat {
+    Png8,
+    Png16,
+    Exr, //32-bit float, linear
+}
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Png8 => "8-bit PNG",
+            ImageFormat::Png16 => "16-bit PNG",
+            ImageFormat::Exr => "32-bit float EXR",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png8 | ImageFormat::Png16 => "png",
+            ImageFormat::Exr => "exr",
+        }
+    }
+
+    /// The 8-bit one for .png
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png8),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+/// The histogram as `format` stores it. For PNG that's what the render pass would show, sRGB in [0,1], and with
+/// `alpha` the background is left out and the colors are straight, not premultiplied. EXR gets the same but linear,
+/// premultiplied and unclamped, so highlights past white are kept. Without `alpha`, alpha is 1.
+pub fn tone_map_image(histogram: &[[f32; 4]], tone_map: &ToneMapStruct, curves: &[[f32; 4]], format: ImageFormat, alpha: bool) -> Vec<[f32; 4]> {
+    let tone_map = if alpha { ToneMapStruct { background: [0.0, 0.0, 0.0, 0.0], ..*tone_map } } else { *tone_map };
+    histogram.iter()
+        .map(|acc| {
+            if format == ImageFormat::Exr {
+                let (color, a) = tone_map_pixel_unclamped(*acc, &tone_map, curves);
+                if !alpha {
+                    let [r, g, b] = apply_color_curves_unclamped(color, curves).map(srgb_to_linear);
+                    return [r, g, b, 1.0];
+                }
+                if a <= 0.0 {
+                    return [0.0; 4];
+                }
+                let [r, g, b] = apply_color_curves_unclamped(color.map(|c| c / a), curves).map(|c| srgb_to_linear(c) * a);
+                return [r, g, b, a];
+            }
+            let (color, a) = tone_map_pixel(*acc, &tone_map, curves);
+            if !alpha {
+                let [r, g, b] = apply_color_curves(color, curves);
+                return [r, g, b, 1.0];
+            }
+            if a <= 0.0 {
+                return [0.0; 4];
+            }
+            let [r, g, b] = apply_color_curves(color.map(|c| (c / a).min(1.0)), curves);
+            [r, g, b, a]
+        })
+        .collect()
+}
+
+/// Writes `pixels` from tone_map_image for the same format, rows top to bottom
+pub fn save_image(path: &Path, format: ImageFormat, width: u32, height: u32, pixels: &[[f32; 4]], alpha: bool) -> Result<()> {
+    if pixels.len() != width as usize * height as usize {
+        bail!("Expected {width}x{height} pixels, got {}", pixels.len());
+    }
+    let channels = if alpha { 4 } else { 3 };
+    let samples = pixels.iter().flat_map(|p| p[..channels].iter().copied());
+    let image: DynamicImage = match format {
+        ImageFormat::Png8 => {
+            let data = samples.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+            if alpha { RgbaImage::from_raw(width, height, data).map(Into::into) } else { RgbImage::from_raw(width, height, data).map(Into::into) }
+        }
+        ImageFormat::Png16 => {
+            let data = samples.map(|c| (c.clamp(0.0, 1.0) * 65535.0).round() as u16).collect();
+            if alpha { ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data).map(Into::into) } else { ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data).map(Into::into) }
+        }
+        ImageFormat::Exr => {
+            let data = samples.collect();
+            if alpha { Rgba32FImage::from_raw(width, height, data).map(Into::into) } else { Rgb32FImage::from_raw(width, height, data).map(Into::into) }
+        }
+    }
+        .context("The pixels don't fill the image")?;
+    let output = if format == ImageFormat::Exr { image::ImageOutputFormat::OpenExr } else { image::ImageOutputFormat::Png };
+    let file = File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    image.write_to(&mut out, output)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(out.flush()?))
+        .with_context(|| format!("Couldn't write {}", path.display()))
+}
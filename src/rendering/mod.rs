@@ -2,3 +2,10 @@ pub mod graphics_engine;
 pub mod gpu_structs;
 pub mod pipeline_compute;
 pub mod pipeline_render;
+pub mod transform_validation;
+pub mod gpu_renderer;
+pub mod cpu_renderer;
+pub mod tone_map;
+pub mod tonal_histogram;
+pub mod accumulation;
+pub mod image_export;
@@ -1,7 +1,7 @@
 use std::mem::size_of;
 use std::rc::Rc;
 use std::sync::Arc;
-use egui_wgpu::RenderState;
+use crate::rendering::gpu_renderer::Gpu;
 use wgpu::{BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource,
            BufferAddress, BufferDescriptor, BufferUsages, ComputePipelineDescriptor,
            PipelineLayoutDescriptor, ShaderStages};
@@ -12,6 +12,7 @@ use wgpu::*;
 use crate::rendering::graphics_engine::*;
 
 pub const WORKGROUP_SIZE: usize = 256;
+pub const POINTS_STATE_SIZE: usize = size_of::<f32>() * 8 * WORKGROUP_SIZE; //bytes, a point and its color per invocation
 
 pub struct Compute {
     pub histogram_buffer: Buffer,
@@ -24,26 +25,27 @@ pub struct Compute {
     pub vec3_params_buffer: Buffer,
     pub parameters_buffer: Buffer,
     pub next_sample_buffer: Buffer,
+    pub accepted_samples_buffer: Buffer, //hits that made it into the histogram, wrapping, see GpuRenderer::accepted_samples
 
     pub bind_group_layout: Arc<BindGroupLayout>,
     pub bind_group: Arc<BindGroup>,
 
     pub pipeline_layout: PipelineLayout,
-    pub compute_pipeline: ComputePipeline,
+    pub compute_pipeline: Arc<ComputePipeline>, //shared with the engine's kernel cache
 }
 
 impl Compute {
-    pub fn init(wgpu: &RenderState, shader: &ShaderModule) -> Self {
+    pub fn init(wgpu: &Gpu, shader: &ShaderModule) -> Self {
         let histogram_buffer = wgpu.device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Histogram buffer"),
             contents: &vec![0u8; HISTOGRAM_WIDTH * HISTOGRAM_HEIGHT * size_of::<[f32;4]>()], // assuming RGBA8?
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
         });
 
         let state_buffer = wgpu.device.create_buffer_init(&BufferInitDescriptor {
             label: None,
-            contents: &vec![0u8; size_of::<f32>() * 8 * WORKGROUP_SIZE],
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST
+            contents: &vec![0u8; POINTS_STATE_SIZE],
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC, //saved with accumulations
         });
 
         let settings_buffer = wgpu.device.create_buffer_init(&BufferInitDescriptor {
@@ -61,8 +63,8 @@ impl Compute {
 
         let alias_tables_buffer = wgpu.device.create_buffer(&BufferDescriptor {
             label: None,
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            size: (MAX_ITERATORS * size_of::<[f32; 4]>()) as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST, //a full xaos table is too big for a uniform
+            size: (MAX_XAOS * size_of::<[f32; 4]>()) as BufferAddress,
             mapped_at_creation: false,
         });
 
@@ -101,6 +103,13 @@ impl Compute {
             mapped_at_creation: false,
         });
 
+        let accepted_samples_buffer = wgpu.device.create_buffer(&BufferDescriptor {
+            label: Some("Accepted samples buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            size: size_of::<u32>() as BufferAddress,
+            mapped_at_creation: false,
+        });
+
         let bind_group_layout = wgpu.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: None,
             entries: &[
@@ -146,9 +155,9 @@ impl Compute {
                 },
                 BindGroupLayoutEntry {
                     binding: 4,
-                    visibility: wgpu::ShaderStages::all(),
+                    visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
-                        ty: Uniform,
+                        ty: Storage { read_only: true, },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -203,6 +212,16 @@ impl Compute {
                         min_binding_size: None,
                     },
                     count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: Storage { read_only: false, },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 }
             ],
         });
@@ -250,6 +269,10 @@ impl Compute {
                 BindGroupEntry {
                     binding: 9,
                     resource: BindingResource::Buffer(next_sample_buffer.as_entire_buffer_binding())
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::Buffer(accepted_samples_buffer.as_entire_buffer_binding())
                 }
             ],
         });
@@ -277,16 +300,17 @@ impl Compute {
             vec3_params_buffer,
             parameters_buffer,
             next_sample_buffer,
+            accepted_samples_buffer,
 
             bind_group: Arc::new(bind_group),
             bind_group_layout: Arc::new(bind_group_layout),
 
-            compute_pipeline,
+            compute_pipeline: Arc::new(compute_pipeline),
             pipeline_layout: layout,
         }
     }
 
-    pub fn update_bind_group(&mut self, wgpu: &RenderState) {
+    pub fn update_bind_group(&mut self, wgpu: &Gpu) {
         let bind_group = wgpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &self.bind_group_layout,
@@ -330,6 +354,10 @@ impl Compute {
                 BindGroupEntry {
                     binding: 9,
                     resource: BindingResource::Buffer(self.next_sample_buffer.as_entire_buffer_binding())
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::Buffer(self.accepted_samples_buffer.as_entire_buffer_binding())
                 }
             ],
         });
@@ -337,7 +365,7 @@ impl Compute {
         self.bind_group = Arc::new(bind_group);
     }
 
-    pub fn create_pipeline_with(wgpu: &RenderState, layout: &PipelineLayout, shader: &ShaderModule) -> ComputePipeline {
+    pub fn create_pipeline_with(wgpu: &Gpu, layout: &PipelineLayout, shader: &ShaderModule) -> ComputePipeline {
         wgpu.device.create_compute_pipeline(&ComputePipelineDescriptor {
             label: None,
             layout: Some(&layout),
@@ -346,11 +374,11 @@ impl Compute {
         })
     }
 
-    pub fn recreate_pipeline_with_shader(&mut self, wgpu: &RenderState, shader: &ShaderModule) {
-        self.compute_pipeline = Self::create_pipeline_with(wgpu, &self.pipeline_layout, shader);
+    pub fn recreate_pipeline_with_shader(&mut self, wgpu: &Gpu, shader: &ShaderModule) {
+        self.compute_pipeline = Arc::new(Self::create_pipeline_with(wgpu, &self.pipeline_layout, shader));
     }
 
-    pub fn encode_commands(&self, wgpu: &RenderState) -> CommandBuffer {
+    pub fn encode_commands(&self, wgpu: &Gpu) -> CommandBuffer {
         let mut encoder = wgpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
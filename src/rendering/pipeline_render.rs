@@ -1,7 +1,11 @@
 use std::sync::Arc;
-use egui_wgpu::RenderState;
+use crate::rendering::gpu_renderer::Gpu;
 use wgpu::*;
 use wgpu::TextureFormat::Rgba8UnormSrgb;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use crate::model::response_curves::ResponseCurves;
+use crate::rendering::graphics_engine::RESPONSE_CURVE_SIZE;
+use crate::rendering::gpu_structs::{Bufferable, ToneMapStruct};
 
 
 pub struct Render {
@@ -10,13 +14,18 @@ pub struct Render {
     pub texture: Texture,
     pub texture_view: TextureView,
     pub pipeline_layout: PipelineLayout,
-    pub pipeline: RenderPipeline,
+    pub pipeline: Arc<RenderPipeline>, //shared with the engine's kernel cache
     pub bind_group_layout: Arc<BindGroupLayout>,
     pub bind_group: Arc<BindGroup>,
+
+    // group 1, only fs_main reads it, so it can change without touching the histogram
+    pub tone_map_buffer: Buffer,
+    pub response_curve_buffer: Buffer,
+    pub tone_map_bind_group: BindGroup,
 }
 
 impl Render {
-    pub(crate) fn resize(&mut self, wgpu: &RenderState, size: (u32, u32)) {
+    pub(crate) fn resize(&mut self, wgpu: &Gpu, size: (u32, u32)) {
         let (width, height) = size;
 
         let old_tex = std::mem::replace(&mut self.texture, wgpu.device.create_texture(&TextureDescriptor {
@@ -47,7 +56,7 @@ impl Render {
         //old_tex.destroy();
     }
 
-    pub fn init(wgpu: &RenderState, shader: &ShaderModule, bind_group_layout: Arc<BindGroupLayout>, bind_group: Arc<BindGroup>, texture_size: (u32, u32)) -> Self {
+    pub fn init(wgpu: &Gpu, shader: &ShaderModule, bind_group_layout: Arc<BindGroupLayout>, bind_group: Arc<BindGroup>, texture_size: (u32, u32)) -> Self {
         let (width, height) = texture_size;
 
         let draw_tex = wgpu.device.create_texture(&TextureDescriptor {
@@ -65,10 +74,63 @@ impl Render {
             view_formats: &[],
         });
 
+        let tone_map_buffer = wgpu.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Tone map buffer"),
+            contents: bytemuck::bytes_of(&ToneMapStruct::new()),
+            usage: ToneMapStruct::desc().usage | BufferUsages::COPY_DST,
+        });
+
+        let response_curve_buffer = wgpu.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Response curve buffer"),
+            contents: bytemuck::cast_slice(&ResponseCurves::default().bake(RESPONSE_CURVE_SIZE)),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        let tone_map_bind_group_layout = wgpu.device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Tone map bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let tone_map_bind_group = wgpu.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Tone map bind group"),
+            layout: &tone_map_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(tone_map_buffer.as_entire_buffer_binding()),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(response_curve_buffer.as_entire_buffer_binding()),
+                },
+            ],
+        });
+
         let render_pipeline_layout =
           wgpu.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
               label: Some("Render Pipeline Layout"),
-              bind_group_layouts: &[&bind_group_layout],
+              bind_group_layouts: &[&bind_group_layout, &tone_map_bind_group_layout],
               push_constant_ranges: &[],
           });
 
@@ -87,13 +149,25 @@ impl Render {
             texture: draw_tex,
             texture_view: tex_view,
             pipeline_layout: render_pipeline_layout,
-            pipeline: render_pipeline,
+            pipeline: Arc::new(render_pipeline),
             bind_group,
             bind_group_layout,
+            tone_map_buffer,
+            response_curve_buffer,
+            tone_map_bind_group,
         }
     }
 
-    pub fn create_pipeline_with(wgpu: &RenderState, layout: &PipelineLayout, shader: &ShaderModule) -> RenderPipeline {
+    pub fn write_tone_map(&self, wgpu: &Gpu, tone_map: &ToneMapStruct) {
+        wgpu.queue.write_buffer(&self.tone_map_buffer, 0 as BufferAddress, bytemuck::bytes_of(tone_map));
+    }
+
+    /// `RESPONSE_CURVE_SIZE` entries, from `ResponseCurves::bake`
+    pub fn write_response_curves(&self, wgpu: &Gpu, table: &[[f32; 4]]) {
+        wgpu.queue.write_buffer(&self.response_curve_buffer, 0 as BufferAddress, bytemuck::cast_slice(table));
+    }
+
+    pub fn create_pipeline_with(wgpu: &Gpu, layout: &PipelineLayout, shader: &ShaderModule) -> RenderPipeline {
         wgpu.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&layout),
@@ -134,11 +208,11 @@ impl Render {
     }
 
 
-    pub fn recreate_pipeline_with_shader(&mut self, wgpu: &RenderState, shader: &ShaderModule) {
-        self.pipeline = Self::create_pipeline_with(wgpu, &self.pipeline_layout, shader);
+    pub fn recreate_pipeline_with_shader(&mut self, wgpu: &Gpu, shader: &ShaderModule) {
+        self.pipeline = Arc::new(Self::create_pipeline_with(wgpu, &self.pipeline_layout, shader));
     }
 
-    pub fn encode_commands(&self, wgpu: &RenderState) -> CommandBuffer {
+    pub fn encode_commands(&self, wgpu: &Gpu) -> CommandBuffer {
         let mut encoder = wgpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
@@ -172,6 +246,7 @@ impl Render {
 
             render_pass.set_pipeline(&self.pipeline);
             render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.set_bind_group(1, &self.tone_map_bind_group, &[]);
             render_pass.draw(0..3, 0..1);
         };
 
@@ -0,0 +1,46 @@
+//! How the tones in the image are spread, what the response curves are drawn over
+
+use crate::rendering::gpu_structs::ToneMapStruct;
+use crate::rendering::tone_map::tone_map_pixel;
+
+pub const TONAL_BINS: usize = 256;
+
+/// Counts of pixels per tone, from 0 to 1 of sRGB. Luminance is Rec. 709's weighting of the three.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TonalHistogram {
+    pub luminance: Vec<u32>,
+    pub red: Vec<u32>,
+    pub green: Vec<u32>,
+    pub blue: Vec<u32>,
+}
+
+impl Default for TonalHistogram {
+    fn default() -> Self {
+        Self {
+            luminance: vec![0; TONAL_BINS],
+            red: vec![0; TONAL_BINS],
+            green: vec![0; TONAL_BINS],
+            blue: vec![0; TONAL_BINS],
+        }
+    }
+}
+
+fn bin(v: f32) -> usize {
+    ((v.clamp(0.0, 1.0) * (TONAL_BINS - 1) as f32).round() as usize).min(TONAL_BINS - 1)
+}
+
+impl TonalHistogram {
+    /// From accumulated histogram pixels, tone mapped like the render pass does but before the color curves,
+    /// since that's what they're applied to
+    pub fn from_samples(samples: &[[f32; 4]], tone_map: &ToneMapStruct, curves: &[[f32; 4]]) -> Self {
+        let mut histogram = Self::default();
+        for acc in samples {
+            let ([r, g, b], _) = tone_map_pixel(*acc, tone_map, curves);
+            histogram.red[bin(r)] += 1;
+            histogram.green[bin(g)] += 1;
+            histogram.blue[bin(b)] += 1;
+            histogram.luminance[bin(0.2126 * r + 0.7152 * g + 0.0722 * b)] += 1;
+        }
+        histogram
+    }
+}
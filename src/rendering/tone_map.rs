@@ -0,0 +1,59 @@
+//! The render pass's tone mapping on the CPU, for whatever needs the image without drawing it.
+//! Keep in step with fs_main and response_curve in ifs_kernel.wgsl.
+
+use std::f32::consts::LN_10;
+use crate::rendering::gpu_structs::ToneMapStruct;
+
+/// One channel of a table from `ResponseCurves::bake`, linear between entries. An empty table changes nothing.
+pub fn response_curve(table: &[[f32; 4]], x: f32, channel: usize) -> f32 {
+    if table.is_empty() {
+        return x;
+    }
+    let last = table.len() - 1;
+    let pos = x.clamp(0.0, 1.0) * last as f32;
+    let i = (pos as usize).min(last);
+    let j = (i + 1).min(last);
+    table[i][channel] + (table[j][channel] - table[i][channel]) * (pos - i as f32)
+}
+
+/// An accumulated histogram pixel as sRGB over the background, with the alpha curve applied but not the color
+/// curves yet. Also returns how much of the pixel isn't background.
+pub fn tone_map_pixel(acc: [f32; 4], tone_map: &ToneMapStruct, curves: &[[f32; 4]]) -> ([f32; 3], f32) {
+    let (color, a) = tone_map_pixel_unclamped(acc, tone_map, curves);
+    (color.map(|c| c.clamp(0.0, 1.0)), a)
+}
+
+/// tone_map_pixel before the color is clamped to what a screen shows, for formats that keep the highlights
+pub fn tone_map_pixel_unclamped(acc: [f32; 4], tone_map: &ToneMapStruct, curves: &[[f32; 4]]) -> ([f32; 3], f32) {
+    let mut color = [0.0; 3];
+    let mut a = 0.0;
+    if acc[3] > 0.0 {
+        let ls = tone_map.brightness * (1.0 + acc[3] * tone_map.density_scale).ln() / (LN_10 * acc[3]);
+        let alpha = acc[3] * ls;
+        let rgb = [acc[0] * ls, acc[1] * ls, acc[2] * ls];
+
+        let g = tone_map.gamma_inv;
+        let mut funcval = alpha.powf(g);
+        if alpha < tone_map.gamma_thresh {
+            let frac = alpha / tone_map.gamma_thresh;
+            funcval = (1.0 - frac) * alpha * tone_map.gamma_thresh.powf(g) / tone_map.gamma_thresh + frac * funcval;
+        }
+
+        let vib = tone_map.vibrancy;
+        color = rgb.map(|c| vib * funcval / alpha * c + (1.0 - vib) * c.max(0.0).powf(g));
+        a = funcval.clamp(0.0, 1.0);
+    }
+    let a = response_curve(curves, a, 3);
+    let bg = tone_map.background;
+    ([0, 1, 2].map(|k| color[k] + (1.0 - a) * bg[k]), a)
+}
+
+/// The red, green and blue curves over a color from `tone_map_pixel`, what the output texture gets
+pub fn apply_color_curves(color: [f32; 3], curves: &[[f32; 4]]) -> [f32; 3] {
+    [0, 1, 2].map(|k| response_curve(curves, color[k], k))
+}
+
+/// apply_color_curves for a color from `tone_map_pixel_unclamped`. Past 1, each channel is scaled by where its curve ends.
+pub fn apply_color_curves_unclamped(color: [f32; 3], curves: &[[f32; 4]]) -> [f32; 3] {
+    [0, 1, 2].map(|k| if color[k] > 1.0 { response_curve(curves, 1.0, k) * color[k] } else { response_curve(curves, color[k], k) })
+}
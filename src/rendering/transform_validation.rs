@@ -0,0 +1,92 @@
+//! Transforms are spliced into the kernel as source, so a broken one used to surface as a wgpu panic
+//! on the render thread. Each transform is checked with naga on its own first, with errors pointed
+//! back at the line of the transform's TOML they came from.
+
+use std::error::Error;
+use std::fmt;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use crate::model::transform::Transform;
+
+/// Where `apply_transform` in ifs_kernel.wgsl takes the transform branches
+pub const TRANSFORMS_MARKER: &str = "@transforms";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformError {
+    pub iterator_id: Option<i32>, //filled in by the engine, a transform can be shared by several iterators
+    pub transform: String, //"name version", empty if the kernel itself is at fault
+    pub line: Option<usize>, //in the transform's TOML, or its source if it wasn't loaded from one
+    pub message: String,
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.line, self.transform.is_empty()) {
+            (_, true) => write!(f, "{}", self.message),
+            (Some(line), false) => write!(f, "{} line {}: {}", self.transform, line, self.message),
+            (None, false) => write!(f, "{}: {}", self.transform, self.message),
+        }
+    }
+}
+
+/// The kernel code for one transform, and how many lines precede the transform's own source in it.
+/// Params are read from the slices of the param buffers the iterator points at, so changing their values
+/// doesn't need a new shader.
+pub fn transform_branch(tf_id: usize, tf: &Transform) -> (String, usize) {
+    let mut prefix = format!("\n    if (iter.tf_id == {tf_id}) {{");
+    for (k, name) in tf.real_param_names().iter().enumerate() {
+        prefix.push_str(&format!("\n        var {name} = real_params[iter.real_params_index + {k}].val;"));
+    }
+    for (k, name) in tf.vec3_param_names().iter().enumerate() {
+        prefix.push_str(&format!("\n        var {name} = vec3_params[iter.vec3_params_index + {k}].xyz;"));
+    }
+    prefix.push('\n');
+    let offset = prefix.matches('\n').count();
+    (format!("{prefix}{}\n    }}", tf.source_code), offset)
+}
+
+/// Parses and validates a complete shader, returning the message and the lines it points at
+pub fn validate_wgsl(src: &str) -> Result<(), (String, Vec<usize>)> {
+    let module = naga::front::wgsl::parse_str(src).map_err(|e| {
+        let lines = e.location(src).map(|l| l.line_number as usize).into_iter().collect();
+        (e.message().to_owned(), lines)
+    })?;
+    Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module).map_err(|e| {
+        let lines = e.spans()
+            .filter(|(span, _)| span.is_defined())
+            .map(|(span, _)| span.location(src).line_number as usize)
+            .collect();
+        //the outer errors only say which function was invalid, the cause is at the bottom
+        let mut message = e.as_inner().to_string();
+        let mut cause: &dyn Error = e.as_inner();
+        while let Some(next) = cause.source() {
+            message = format!("{message}: {next}");
+            cause = next;
+        }
+        (message, lines)
+    })?;
+    Ok(())
+}
+
+/// Checks a transform in isolation, spliced into `kernel` as the only branch of `apply_transform`
+pub fn validate_transform(kernel: &str, tf: &Transform) -> Result<(), TransformError> {
+    let error = |line, message| TransformError {
+        iterator_id: None,
+        transform: format!("{} {}", tf.name, tf.version),
+        line,
+        message,
+    };
+    let Some(marker) = kernel.find(TRANSFORMS_MARKER) else {
+        return Err(error(None, format!("Kernel has no {TRANSFORMS_MARKER} marker")));
+    };
+    let (branch, offset) = transform_branch(0, tf);
+    let first_line = kernel[..marker].matches('\n').count() + 1 + offset;
+    let last_line = first_line + tf.source_code.lines().count();
+    let src = kernel.replacen(TRANSFORMS_MARKER, &branch, 1);
+
+    validate_wgsl(&src).map_err(|(message, lines)| {
+        let line = lines.into_iter()
+            .find(|l| (first_line..last_line).contains(l))
+            .map(|l| l - first_line + tf.source_line.max(1));
+        error(line, message)
+    })
+}
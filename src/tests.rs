@@ -1,4 +1,31 @@
+use crate::alias_method::{alias_table, AliasEntry};
+use crate::editors::automation_editor::automation_editor::AutomationEditor;
+use crate::editors::curve_widget::eval_curve;
+use crate::editors::palette_editor::bake_hsv_curves;
 use crate::model::camera::Camera;
+use crate::model::ifs::IFS;
+use crate::model::iterator::{IdAllocator, Iterator};
+use crate::model::palette::Palette;
+use crate::model::palette_generation::{from_image, generate, kmeans, lightness, smooth, sort_by_lightness, Scheme};
+use crate::model::palette_import::{parse_gpl, parse_map, parse_ugr};
+use crate::model::response_curves::ResponseCurves;
+use crate::model::transform::Transform;
+use crate::model::transform_library::TransformLibrary;
+use crate::model::xaos::{Xaos, XaosEdge};
+use crate::util::glsl::glsl_to_wgsl;
+use crate::util::lru_cache::LruCache;
+use crate::util::math_extensions::hsv_to_rgb;
+use crate::rendering::transform_validation::validate_transform;
+use crate::rendering::accumulation::Accumulation;
+use crate::rendering::cpu_renderer::{CpuRenderer, PORTED_TRANSFORMS};
+use crate::rendering::gpu_renderer::{Gpu, GpuRenderer};
+use crate::rendering::graphics_engine::{DEFAULT_SEED, MAX_ITERATORS, MAX_PALETTE_COLORS};
+use crate::rendering::gpu_structs::ToneMapStruct;
+use crate::rendering::image_export::{save_image, tone_map_image, ImageFormat};
+use crate::rendering::tonal_histogram::{TonalHistogram, TONAL_BINS};
+use crate::rendering::pipeline_compute::POINTS_STATE_SIZE;
+use crate::rendering::tone_map::{apply_color_curves, response_curve, tone_map_pixel};
+use sha2::{Digest, Sha256};
 
 #[cfg(test)]
 mod tests {
@@ -294,4 +321,995 @@ mod tests {
         assert!((camera_struct.position[1] - 0.01).abs() < 0.001);
         assert!((camera_struct.position[2] - 0.01).abs() < 0.001);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_load_ifsjson() {
+        let ifs = IFS::from_ifsjson(include_str!("../cube.json"), |name, version| {
+            (name == "Cube" && version == "1.1").then(Transform::cube)
+        }).unwrap();
+
+        assert_eq!(ifs.title, "cube");
+        assert_eq!(ifs.authors[0].name, "Unknown Artist");
+        assert_eq!(ifs.iterators.len(), 1);
+        let it = &ifs.iterators[0];
+        assert_eq!(it.id, 18209397);
+        assert_eq!(it.transform.name, "Cube");
+        assert_eq!(ifs[(it, it)], 0.0);
+        assert_eq!((ifs.width, ifs.height), (1920, 1080));
+        assert_eq!(ifs.stopping_sl, 15.0);
+        assert_eq!(ifs.background_color, [0.0, 0.0, 0.0]);
+        assert_eq!(ifs.palette.colors.len(), 2);
+        assert!((ifs.camera.position.x - -3.7297344).abs() < 0.0001);
+        assert!((ifs.camera.orientation.w - 0.9448469).abs() < 0.0001);
+        assert!((ifs.camera.orientation.i - 0.21461225).abs() < 0.0001);
+        assert_eq!(ifs.camera.fov, 60.0);
+    }
+    #[test]
+    fn test_load_ifsjson_missing_transform() {
+        let err = IFS::from_ifsjson(include_str!("../cube.json"), |_, _| None).unwrap_err();
+        assert!(err.to_string().contains("Cube 1.1"));
+    }
+    #[test]
+    fn test_ifsjson_round_trip() {
+        let find = |name: &str, version: &str| (name == "Cube" && version == "1.1").then(Transform::cube);
+        let ifs = IFS::from_ifsjson(include_str!("../cube.json"), find).unwrap();
+        let exported = ifs.to_ifsjson().unwrap();
+        let reloaded = IFS::from_ifsjson(&exported, find).unwrap();
+
+        assert_eq!(reloaded.title, ifs.title);
+        assert_eq!(reloaded.authors, ifs.authors);
+        assert_eq!(reloaded.iterators.len(), ifs.iterators.len());
+        for (a, b) in reloaded.iterators.iter().zip(&ifs.iterators) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.transform, b.transform);
+            assert_eq!(a.real_params, b.real_params);
+            assert_eq!(a.vec3_params, b.vec3_params);
+            assert_eq!(a.base_weight, b.base_weight);
+            assert_eq!(a.color_index, b.color_index);
+        }
+        assert_eq!(reloaded.xaos, ifs.xaos);
+        assert_eq!((reloaded.width, reloaded.height), (ifs.width, ifs.height));
+        assert_eq!(reloaded.background_color, ifs.background_color);
+        assert_eq!(reloaded.palette, ifs.palette);
+        assert_eq!(reloaded.gamma_inv, ifs.gamma_inv);
+        assert_eq!(reloaded.stopping_sl, ifs.stopping_sl);
+        assert_eq!(reloaded.camera.position, ifs.camera.position);
+        assert_eq!(reloaded.camera.orientation, ifs.camera.orientation);
+        assert_eq!(reloaded.camera.focus_distance, ifs.camera.focus_distance);
+
+        // and the schema is stable once it has been through us
+        assert_eq!(reloaded.to_ifsjson().unwrap(), exported);
+        let raw: serde_json::Value = serde_json::from_str(&exported).unwrap();
+        assert_eq!(raw["Iterators"][0]["Transform"]["Item1"], "Cube");
+        assert_eq!(raw["Camera"]["Orientation"]["W"], ifs.camera.orientation.w);
+        assert_eq!(raw["xaos"].as_array().unwrap().len(), 1);
+        assert_eq!(raw["Nodes"].as_array().unwrap().len(), 1);
+
+        // the weight graph layout and animation aren't ours to edit, so they go back as they came
+        let original: serde_json::Value = serde_json::from_str(include_str!("../cube.json").trim_start_matches('\u{feff}')).unwrap();
+        assert_eq!(raw["Nodes"], original["Nodes"]);
+        assert_eq!(raw["Dopesheet"], original["Dopesheet"]);
+        let mut added = ifs.clone();
+        added.add_iterator(Iterator::default(), true);
+        let raw: serde_json::Value = serde_json::from_str(&added.to_ifsjson().unwrap()).unwrap();
+        assert_eq!(raw["Nodes"][0], original["Nodes"][0]);
+        assert_eq!(raw["Nodes"].as_array().unwrap().len(), 2);
+    }
+    #[test]
+    fn test_world_toml_round_trip() {
+        let mut ifs = IFS::cube_example();
+        let mut second = Iterator { id: ifs.iterators[0].id + 1, ..Iterator::default() };
+        second.real_params.insert(String::from("Size"), 2.5);
+        ifs.iterators.push(second);
+        let (a, b) = (ifs.iterators[0].clone(), ifs.iterators[1].clone());
+        ifs.xaos.set(a.id, b.id, 0.75);
+        ifs.xaos.set(b.id, a.id, 1.0);
+
+        let src = ifs.to_world_toml().unwrap();
+        let loaded = IFS::from_world_toml(&src).unwrap();
+
+        assert_eq!(loaded.title, ifs.title);
+        assert_eq!(loaded.iterators.len(), 2);
+        assert_eq!(loaded.iterators[1].real_params["Size"], 2.5);
+        assert_eq!(loaded[(&a, &b)], 0.75);
+        assert_eq!(loaded[(&b, &a)], 1.0);
+        assert_eq!(loaded[(&a, &a)], 1.0); //cube_example loops back on itself
+        assert_eq!(loaded[(&b, &b)], 0.0);
+        assert_eq!(loaded.camera.position, ifs.camera.position);
+        assert_eq!(loaded.palette, ifs.palette);
+        assert_eq!(loaded.to_world_toml().unwrap(), src);
+    }
+    #[test]
+    fn test_world_toml_errors() {
+        let newer = "format_version = 9999\n";
+        assert!(IFS::from_world_toml(newer).unwrap_err().to_string().contains("newer"));
+
+        let unversioned = "title = \"cube\"\n";
+        assert!(IFS::from_world_toml(unversioned).unwrap_err().to_string().contains("format_version"));
+
+        let src = IFS::cube_example().to_world_toml().unwrap().replace("fuse = 20", "fuse = \"twenty\"");
+        let err = format!("{:#}", IFS::from_world_toml(&src).unwrap_err());
+        assert!(err.contains("Malformed") && err.contains("fuse"), "{err}");
+    }
+    #[test]
+    fn test_transform_library() {
+        let lib = TransformLibrary::load(&[std::path::PathBuf::from("transforms")]);
+        assert!(lib.errors.is_empty(), "{:?}", lib.errors);
+        assert_eq!(lib.len(), 3);
+        let affine = lib.get("Affine", "1.1").expect("numeric versions should load too");
+        assert_eq!(affine.vec3_params["Scale"], [1.0, 1.0, 1.0]);
+        assert_eq!(lib.get("Checks", "1.1").unwrap().real_params["Size"], 1.0);
+        assert!(lib.get("Cube", "2.0").is_none());
+
+        //numbers are kept as written, 1.10 comes after 1.9 rather than being 1.1
+        let numbered = |version: &str| Transform::from_toml(&format!("name = \"V\"\nversion = {version}\n[wgsl]\nsrc = 'return p;'\n"));
+        assert_eq!(numbered("1.10").unwrap().version, "1.10");
+        assert_eq!(numbered("2").unwrap().version, "2");
+        assert_eq!(numbered("\"1.10b\"").unwrap().version, "1.10b");
+        assert!(numbered("[1, 10]").is_err());
+
+        let mut it = Iterator::from_transform(lib.get("Checks", "1.1").unwrap().clone());
+        assert_eq!(it.transform.name, "Checks");
+        it.vec3_params.insert(String::from("Offset"), [2.0, 2.0, 2.0]);
+        it.set_transform(affine.clone());
+        assert_eq!(it.transform, *affine);
+        assert!(!it.real_params.contains_key("Size"));
+        assert_eq!(it.vec3_params["Scale"], [1.0, 1.0, 1.0]);
+    }
+    #[test]
+    fn test_transform_library_errors() {
+        let dir = std::env::temp_dir().join(format!("ifsrs_tf_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.toml"), "name = \"Broken\"\n").unwrap();
+        std::fs::copy("transforms/cube.toml", dir.join("cube.toml")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a transform").unwrap();
+
+        let lib = TransformLibrary::load(&[dir.clone(), std::path::PathBuf::from("transforms")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lib.len(), 3);
+        assert_eq!(lib.errors.len(), 2); //broken.toml, and the second Cube 1.1
+        assert!(lib.errors.iter().any(|e| e.path.ends_with("broken.toml") && e.message.contains("version")));
+        assert!(lib.errors.iter().any(|e| e.message.contains("already loaded")));
+    }
+
+    #[test]
+    fn test_glsl_to_wgsl_checks() {
+        let tf = Transform::from_toml(include_str!("../transforms/checks.toml")).unwrap();
+        let expected = "\
+var checks: vec3<f32> = floor(p * Size + vec3<f32>(0.5));
+var checked: f32 = checks.x + checks.y + checks.z;
+if(((checked) - (2.0) * floor((checked) / (2.0))) != 0.0)
+\t{ return p - Offset; }
+else
+\t{ return p + Offset; }
+";
+        assert_eq!(tf.source_code, expected);
+    }
+
+    #[test]
+    fn test_glsl_to_wgsl_affine() {
+        let tf = Transform::from_toml(include_str!("../transforms/affine.toml")).unwrap();
+        let first = tf.source_code.lines().next().unwrap();
+        assert_eq!(first, "var scale: mat3x3<f32> = mat3x3<f32>((1.0), 0.0, 0.0, 0.0, (1.0), 0.0, 0.0, 0.0, (1.0));");
+        //the local `scale` mustn't be mistaken for the Scale param
+        assert!(tf.source_code.contains("scale[0][0] = Scale.x;"));
+        assert!(tf.source_code.contains("* scale;"));
+    }
+
+    #[test]
+    fn test_glsl_to_wgsl_misc() {
+        let src = "const float k = atan(p.y, p.x);\nfor (int i = 0; i < 3; i++)\n    p += vec3(mod(k,\n 1.0));\nfloat r = random(next);";
+        let out = glsl_to_wgsl(src, &[]);
+        assert_eq!(out.lines().count(), src.lines().count());
+        assert!(out.starts_with("let k: f32 = atan2(p.y, p.x);"));
+        assert!(out.contains("for (var i: i32 = 0; i < 3; i++)\n    { p += vec3<f32>(((k) - (1.0) * floor((k) / (1.0))))\n; }"));
+        assert!(out.ends_with("var r: f32 = random();"));
+
+        //WGSL passes through as is
+        let cube = Transform::cube().source_code;
+        assert_eq!(glsl_to_wgsl(&cube, &[]), cube);
+    }
+
+    #[test]
+    fn test_validate_transforms() {
+        let kernel = include_str!("rendering/ifs_kernel.wgsl");
+        for src in [
+            include_str!("../transforms/cube.toml"),
+            include_str!("../transforms/checks.toml"),
+            include_str!("../transforms/affine.toml"),
+        ] {
+            let tf = Transform::from_toml(src).unwrap();
+            if let Err(e) = validate_transform(kernel, &tf) {
+                panic!("{e}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_transform_errors() {
+        let kernel = include_str!("rendering/ifs_kernel.wgsl");
+        let src = "name = \"Broken\"\nversion = 1\n\n[real_params]\nSize = 1\n\n[wgsl]\nsrc = '''\nlet a = p * Size;\nreturn a + undefined_thing;\n'''\n";
+        let tf = Transform::from_toml(src).unwrap();
+        assert_eq!(tf.source_line, 9);
+        let e = validate_transform(kernel, &tf).unwrap_err();
+        assert_eq!(e.transform, "Broken 1");
+        assert_eq!(e.line, Some(10));
+        assert!(e.message.contains("undefined_thing"), "{}", e.message);
+
+        //type errors only show up in validation, after parsing
+        let tf = Transform::from_toml(&src.replace("undefined_thing", "1u")).unwrap();
+        let e = validate_transform(kernel, &tf).unwrap_err();
+        assert_eq!(e.line, Some(10), "{e}");
+    }
+
+    #[test]
+    fn test_ifs_hash_tracks_iterator_values() {
+        let mut ifs = IFS::default();
+        ifs.iterators[0] = Iterator::from_transform(Transform::from_toml(include_str!("../transforms/checks.toml")).unwrap());
+        let before = ifs.get_hash();
+        ifs.iterators[0].real_params.insert(String::from("Size"), 2.0);
+        assert_ne!(ifs.get_hash(), before);
+        ifs.iterators[0].real_params.insert(String::from("Size"), 1.0);
+        assert_eq!(ifs.get_hash(), before);
+        ifs.iterators[0].color_speed += 0.1;
+        assert_ne!(ifs.get_hash(), before);
+
+        let tf = &ifs.iterators[0].transform;
+        assert_eq!(tf.real_param_names(), vec!["Size"]);
+        assert_eq!(Transform::from_toml(include_str!("../transforms/affine.toml")).unwrap().vec3_param_names(),
+                   vec!["Rotate", "Scale", "Translate"]);
+    }
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.insert(1, "a").is_none());
+        assert!(cache.insert(2, "b").is_none());
+        assert_eq!(cache.get(&1), Some(&"a")); //1 is now the most recent
+        assert_eq!(cache.insert(3, "c"), Some((2, "b")));
+        assert!(cache.contains(&1) && cache.contains(&3) && !cache.contains(&2));
+        assert!(cache.insert(3, "c2").is_none()); //replacing doesn't evict
+        assert_eq!(cache.get(&3), Some(&"c2"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    /// How often each index comes out of the table, worked out exactly from its columns
+    fn alias_frequencies(table: &[AliasEntry]) -> Vec<f64> {
+        let n = table.len() as f64;
+        let mut freq = vec![0.0; table.len()];
+        for (i, e) in table.iter().enumerate() {
+            freq[i] += e.prob as f64 / n;
+            if e.alias >= 0 {
+                freq[e.alias as usize] += (1.0 - e.prob as f64) / n;
+            }
+        }
+        freq
+    }
+
+    #[test]
+    fn test_alias_table() {
+        let weights = [1.0, 2.0, 0.0, 5.0, 0.5];
+        let table = alias_table(&weights);
+        let sum: f64 = weights.iter().sum();
+        for (f, w) in alias_frequencies(&table).iter().zip(weights) {
+            assert!((f - w / sum).abs() < 1e-6, "{f} vs {}", w / sum);
+        }
+        assert_eq!(table[2].prob, 0.0); //never keeps a zero weight column
+
+        let uniform = alias_table(&[3.0; 4]);
+        assert!(uniform.iter().enumerate().all(|(i, e)| e.prob == 1.0 && e.alias == i as i32));
+
+        //nowhere to go
+        assert!(alias_table(&[0.0, 0.0, -1.0]).iter().all(|e| e.alias == -1 && e.prob == 0.0));
+        assert!(alias_table(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_xaos() {
+        let mut xaos = Xaos::default();
+        xaos.set(1, 2, 3.0);
+        xaos.set(1, 3, 1.0);
+        xaos.set(2, 1, 0.5);
+        xaos.set(3, 3, xaos.get(3, 3) + 2.0);
+        assert_eq!(xaos.get(1, 2), 3.0);
+        assert_eq!(xaos[(2, 3)], 0.0);
+
+        //an edge that's been zeroed is no different from one that was never there
+        let mut zeroed = xaos.clone();
+        zeroed.set(4, 1, 1.0);
+        zeroed.set(4, 1, 0.0);
+        assert_eq!(zeroed, xaos);
+        let ifs = IFS::default();
+        let ifs_hash = |xaos: &Xaos| IFS { xaos: xaos.clone(), ..ifs.clone() }.get_hash();
+        assert_eq!(ifs_hash(&zeroed), ifs_hash(&xaos));
+        assert_eq!(serde_json::to_string(&zeroed).unwrap(), serde_json::to_string(&xaos).unwrap());
+        assert_eq!(xaos.outgoing(1).collect::<Vec<_>>(), vec![(2, 3.0), (3, 1.0)]);
+        assert_eq!(xaos.incoming(3).collect::<Vec<_>>(), vec![(1, 1.0), (3, 2.0)]);
+
+        xaos.normalize(1);
+        assert_eq!(xaos.outgoing(1).collect::<Vec<_>>(), vec![(2, 0.75), (3, 0.25)]);
+
+        xaos.set(1, 2, 0.0);
+        assert_eq!(xaos.outgoing(1).count(), 1);
+        xaos.remove_iterator(3);
+        assert_eq!(xaos.edges().collect::<Vec<_>>(), vec![XaosEdge { from: 2, to: 1, weight: 0.5 }]);
+    }
+
+    #[test]
+    fn test_world_toml_xaos() {
+        let mut ifs = IFS::cube_example();
+        let id = ifs.iterators[0].id;
+        ifs.xaos.set(id, id, 0.5);
+        //the weights are part of the world
+        let saved = ifs.to_world_toml().unwrap();
+        assert!(saved.contains("[[world.xaos]]"));
+        assert_eq!(IFS::from_world_toml(&saved).unwrap().xaos.get(id, id), 0.5);
+
+        let dangling = ifs.to_world_toml().unwrap() + &format!("[[world.xaos]]\nfrom = {id}\nto = 12345\nweight = 1.0\n");
+        assert!(IFS::from_world_toml(&dangling).unwrap_err().to_string().contains("12345"));
+    }
+
+    #[test]
+    fn test_add_dup_del_iterators() {
+        let mut ifs = IFS::default();
+        let a = ifs.iterators[0].id;
+        let b = ifs.add_iterator(Iterator { id: a + 1, ..Iterator::default() }, true).unwrap();
+        assert_eq!([ifs.xaos.get(a, b), ifs.xaos.get(b, a), ifs.xaos.get(b, b)], [1.0; 3]);
+        let c = ifs.add_iterator(Iterator { id: a + 2, ..Iterator::default() }, false).unwrap();
+        assert_eq!(ifs.xaos.outgoing(c).count() + ifs.xaos.incoming(c).count(), 0);
+
+        ifs.xaos.set(a, b, 0.25);
+        ifs.xaos.set(c, a, 3.0);
+        let d = ifs.dup_iterator(a, true).unwrap();
+        assert_ne!(d, a);
+        assert_eq!(ifs.iterators.len(), 4);
+        assert_eq!(ifs.iterators[0].base_weight, 0.5);
+        assert_eq!(ifs.iterators[3].base_weight, 0.5);
+        assert_eq!(ifs.xaos.get(d, b), 0.25); //outgoing copied
+        assert_eq!(ifs.xaos.get(c, d), 3.0); //incoming copied
+        assert_eq!([ifs.xaos.get(a, d), ifs.xaos.get(d, a), ifs.xaos.get(d, d)], [1.0; 3]); //from a's loop
+        assert!(ifs.dup_iterator(12345, false).is_none());
+
+        assert!(ifs.reorder_iterator(d, 0));
+        assert_eq!(ifs.iterators.iter().map(|it| it.id).collect::<Vec<_>>(), vec![d, a, b, c]);
+        assert!(ifs.reorder_iterator(d, 99));
+        assert_eq!(ifs.iterators.last().unwrap().id, d);
+
+        let removed = ifs.del_iterator(a).unwrap();
+        assert_eq!(removed.id, a);
+        assert!(ifs.xaos.edges().all(|e| e.from != a && e.to != a));
+        assert!(ifs.del_iterator(a).is_none());
+        assert_eq!(ifs.iterators.len(), 3);
+    }
+
+    #[test]
+    fn test_iterator_limit() {
+        let mut ifs = IFS::default();
+        let a = ifs.iterators[0].id;
+        while !ifs.is_full() {
+            ifs.add_iterator(Iterator::default(), false).unwrap();
+        }
+        assert_eq!(ifs.iterators.len(), MAX_ITERATORS);
+        assert!(ifs.add_iterator(Iterator::default(), true).is_none());
+        assert!(ifs.dup_iterator(a, true).is_none());
+        assert_eq!(ifs[a].base_weight, 1.0);
+        assert_eq!(ifs.iterators.len(), MAX_ITERATORS);
+
+        //worlds that wouldn't fit in the kernel are turned away by both loaders
+        assert!(IFS::from_world_toml(&ifs.to_world_toml().unwrap()).is_ok());
+        ifs.iterators.push(Iterator::default());
+        assert!(IFS::from_world_toml(&ifs.to_world_toml().unwrap()).unwrap_err().to_string().contains("iterators"));
+        let ifsjson = ifs.to_ifsjson().unwrap();
+        let loaded = IFS::from_ifsjson(&ifsjson, |name, version| (name == "Cube" && version == "1.1").then(Transform::cube));
+        assert!(loaded.unwrap_err().to_string().contains("iterators"));
+    }
+
+    #[test]
+    fn test_iterator_ids() {
+        let mut ifs = IFS::default();
+        let a = ifs.iterators[0].id;
+        assert_eq!(ifs[a].id, a);
+        ifs[a].opacity = 0.5;
+        assert_eq!(ifs.get(a).unwrap().opacity, 0.5);
+        assert!(ifs.get(a.wrapping_add(1000)).is_none());
+
+        //a copy of an existing id gets a new one
+        let b = ifs.add_iterator(ifs[a].clone(), false).unwrap();
+        assert_ne!(a, b);
+        assert_ne!(Iterator::default().id, Iterator::default().id);
+
+        //ids read from files are never handed out again, and running out doesn't wrap around
+        let ids = IdAllocator::new(1);
+        ids.reserve_id(41);
+        assert_eq!(ids.next_id(), Some(42));
+        ids.reserve_id(7);
+        assert_eq!(ids.next_id(), Some(43));
+        ids.reserve_id(i32::MAX - 1);
+        assert_eq!(ids.next_id(), Some(i32::MAX));
+        assert_eq!(ids.next_id(), None);
+        assert_eq!(ids.next_id(), None);
+
+        //then a world picks a free one itself
+        let mut small = IFS::default();
+        small.iterators[0].id = 1;
+        let mut candidates = [1, 5].into_iter();
+        assert_eq!(small.unused_id(|| candidates.next()), 5);
+        assert_eq!(small.unused_id(|| ids.next_id()), 2);
+
+        let mut dupes = IFS::default();
+        dupes.iterators.push(dupes.iterators[0].clone());
+        assert!(dupes.claim_ids().is_err());
+    }
+
+    #[test]
+    fn test_delete_automated_iterator() {
+        let mut ifs = IFS::cube_example();
+        let kept = ifs.iterators[0].id;
+        let deleted = ifs.add_iterator(Iterator::from_transform(Transform::cube()), true).unwrap();
+        let mut editor = AutomationEditor::default();
+        let kept_term = editor.update_target(kept, "Kept".into(), "Mix".into());
+        let terms = [
+            editor.update_target(deleted, "Deleted".into(), "Mix".into()),
+            editor.update_target(deleted, "Deleted".into(), "Add".into()),
+        ];
+        assert_ne!(editor.target(kept), editor.target(deleted));
+
+        //as the iterator list does it
+        ifs.del_iterator(deleted);
+        editor.remove_target(deleted);
+        assert!(ifs.iterator_index(deleted).is_none());
+        assert!(editor.target(deleted).is_none());
+        assert!(terms.iter().all(|t| !editor.has_terminal(*t)));
+        assert!(editor.target(kept).is_some());
+        assert!(editor.has_terminal(kept_term));
+
+        //never automated, or already gone
+        editor.remove_target(deleted);
+        assert!(editor.has_terminal(kept_term));
+    }
+
+    #[test]
+    fn test_cpu_renderer() {
+        let lib = TransformLibrary::load(&[std::path::PathBuf::from("transforms")]);
+        let mut ifs = IFS::cube_example();
+        ifs.width = 64;
+        ifs.height = 48;
+        let affine = ifs.add_iterator(Iterator::from_transform(lib.get("Affine", "1.1").unwrap().clone()), true).unwrap();
+        ifs[affine].vec3_params.insert("Scale".into(), [0.5, 0.5, 0.5]);
+        ifs.add_iterator(Iterator::from_transform(lib.get("Checks", "1.1").unwrap().clone()), true);
+
+        let render = |seed| {
+            let mut renderer = CpuRenderer::new(&ifs, 3, seed).unwrap();
+            renderer.dispatch(2000);
+            renderer.dispatch(2000);
+            assert_eq!(renderer.dispatch_count(), 2);
+            renderer.histogram()
+        };
+        let hist = render(DEFAULT_SEED);
+        assert_eq!(hist.len(), 64 * 48);
+        assert!(hist.iter().filter(|px| px[3] > 0.0).count() > 10);
+        assert!(hist.iter().flatten().all(|c| c.is_finite() && *c >= 0.0));
+
+        //same seed and thread count, same histogram
+        assert_eq!(hist, render(DEFAULT_SEED));
+        assert_ne!(hist, render(DEFAULT_SEED + 1));
+
+        //ports go by name and version, anything else is refused
+        let mut unported = Transform::cube();
+        unported.name = "Mystery".into();
+        let mut newer = Transform::cube();
+        newer.version = "1.2".into();
+        let with = |tf: Transform| {
+            let mut ifs = ifs.clone();
+            ifs.add_iterator(Iterator::from_transform(tf), true);
+            CpuRenderer::new(&ifs, 1, DEFAULT_SEED)
+        };
+        assert!(with(unported).is_err());
+        assert!(with(newer).is_err());
+        assert!(with(Transform::cube()).is_ok());
+    }
+
+    /// The CPU ports were written from these sources. When one changes, bring its port in
+    /// cpu_renderer.rs in line, then the digest here.
+    #[test]
+    fn test_ported_transforms_unchanged() {
+        let ported_from = [
+            ("Cube", "1.1", "834d0b4fb572d35e1944de636b764a0dfb4c5a7256a6e8fcaca8a8c98be41d5d"),
+            ("Checks", "1.1", "535b44177c14063049341e18093ea8ffeef28cf51009be71191944023b4df918"),
+            ("Affine", "1.1", "9395671847e314851d7bc579c956044bec7d0f2d0c01b58e1b3d8888aac3ed7c"),
+        ];
+        assert_eq!(PORTED_TRANSFORMS.len(), ported_from.len());
+        let lib = TransformLibrary::load(&[std::path::PathBuf::from("transforms")]);
+        for (name, version) in PORTED_TRANSFORMS {
+            let (.., digest) = ported_from.iter().find(|(n, v, _)| n == name && v == version)
+                .unwrap_or_else(|| panic!("No source digest for the port of {name} {version}"));
+            let tf = lib.get(name, version).unwrap_or_else(|| panic!("{name} {version} isn't in transforms/"));
+            //line endings made \n first, so a checkout with \r\n still matches
+            let source: String = Sha256::digest(tf.source_code.replace("\r\n", "\n")).iter().map(|b| format!("{b:02x}")).collect();
+            assert_eq!(&source, digest, "{name} {version} changed in transforms/, its CPU port has to follow");
+        }
+    }
+
+    #[test]
+    fn test_sampling_level() {
+        let Ok(gpu) = Gpu::headless(None) else {
+            eprintln!("No adapter, so nothing to render with");
+            return;
+        };
+        let mut ifs = IFS::cube_example();
+        ifs.width = 32;
+        ifs.height = 24;
+        ifs.use_stopping_sl = true;
+        ifs.stopping_sl = 12.0;
+        let mut renderer = GpuRenderer::new(&gpu);
+        renderer.invocation_iters = 32; //past the fuse, but a few dispatches to get there rather than one
+        assert!(renderer.update_model(&gpu, ifs).is_empty());
+        assert_eq!(renderer.sampling_level(), f64::NEG_INFINITY);
+        assert!(!renderer.is_stopped());
+
+        //dispatching only while not stopped, as the engine does
+        let mut levels = vec![];
+        for _ in 0..1000 {
+            if renderer.is_stopped() {
+                break;
+            }
+            renderer.dispatch(&gpu);
+            gpu.device.poll(wgpu::Maintain::Wait);
+            renderer.update_accepted_samples();
+            let level = renderer.sampling_level();
+            assert!(level.is_finite());
+            assert_eq!(level, (renderer.accepted_samples() as f64 / (32.0 * 24.0)).log2());
+            levels.push(level);
+        }
+        assert!(renderer.is_stopped());
+        assert!(levels.len() > 1, "{levels:?}");
+        assert!(levels.windows(2).all(|w| w[0] < w[1]), "{levels:?}");
+        //stopped as soon as the level got there, not a dispatch later
+        assert!(levels[..levels.len() - 1].iter().all(|&l| l < 12.0), "{levels:?}");
+        assert!(levels[levels.len() - 1] >= 12.0);
+        assert_eq!(renderer.dispatch_count() as usize, levels.len());
+    }
+
+    #[test]
+    fn test_cpu_renderer_matches_kernel() {
+        let Ok(gpu) = Gpu::headless(None) else {
+            eprintln!("No adapter, so nothing to check the CPU renderer against");
+            return;
+        };
+        let lib = TransformLibrary::load(&[std::path::PathBuf::from("transforms")]);
+        let mut ifs = IFS::cube_example();
+        ifs.width = 32;
+        ifs.height = 24;
+        let affine = ifs.add_iterator(Iterator::from_transform(lib.get("Affine", "1.1").unwrap().clone()), true).unwrap();
+        ifs[affine].vec3_params.insert("Scale".into(), [0.5, 0.5, 0.5]);
+        ifs[affine].vec3_params.insert("Translate".into(), [0.5, 0.0, 0.0]);
+        ifs.add_iterator(Iterator::from_transform(lib.get("Checks", "1.1").unwrap().clone()), true);
+
+        let mut renderer = GpuRenderer::new(&gpu);
+        assert!(renderer.update_model(&gpu, ifs.clone()).is_empty());
+        renderer.dispatch(&gpu);
+        let readback = renderer.read_histogram(&gpu);
+        gpu.device.poll(wgpu::Maintain::Wait);
+        let bytes = readback.try_take().unwrap().unwrap();
+        let kernel: Vec<[f32; 4]> = bytes.chunks_exact(std::mem::size_of::<[f32; 4]>()).map(bytemuck::pod_read_unaligned).collect();
+
+        let mut cpu = CpuRenderer::new(&ifs, 8, DEFAULT_SEED).unwrap();
+        cpu.dispatch(100_000);
+
+        //the samples can't be the same, the GPU's floats round differently and the points wander apart,
+        //but where the density and color end up has to be: share of the total per 4x4 block, and mean color
+        let summary = |histogram: &[[f32; 4]]| {
+            let total: [f32; 4] = histogram.iter().fold([0.0; 4], |acc, px| [0, 1, 2, 3].map(|k| acc[k] + px[k]));
+            let mut blocks = vec![0.0; 8 * 6];
+            for (i, px) in histogram.iter().enumerate() {
+                blocks[(i % 32) / 4 + (i / 32) / 4 * 8] += px[3] / total[3];
+            }
+            (blocks, [0, 1, 2].map(|k| total[k] / total[3]))
+        };
+        let (kernel_blocks, kernel_color) = summary(&kernel);
+        let (cpu_blocks, cpu_color) = summary(&cpu.histogram());
+        let difference: f32 = kernel_blocks.iter().zip(&cpu_blocks).map(|(a, b)| (a - b).abs()).sum();
+        assert!(difference < 0.02, "{kernel_blocks:?} != {cpu_blocks:?}");
+        for k in 0..3 {
+            assert!((kernel_color[k] - cpu_color[k]).abs() < 0.02, "{kernel_color:?} != {cpu_color:?}");
+        }
+    }
+
+    #[test]
+    fn test_display_settings_keep_histogram() {
+        let ifs = IFS::default();
+        let mut brighter = ifs.clone();
+        brighter.brightness *= 2.0;
+        brighter.background_color = [0.5, 0.5, 0.5];
+        assert_eq!(ifs.get_hash(), brighter.get_hash());
+        assert_ne!(ifs.settings_hash(), brighter.settings_hash());
+
+        let mut longer_fuse = ifs.clone();
+        longer_fuse.fuse += 1;
+        assert_ne!(ifs.get_hash(), longer_fuse.get_hash());
+        assert_eq!(ifs.settings_hash(), longer_fuse.settings_hash());
+
+        let mut curved = ifs.clone();
+        curved.response_curves.red = vec![[0.0, 0.2], [1.0, 0.8]];
+        assert_eq!(ifs.get_hash(), curved.get_hash());
+        assert_ne!(ifs.settings_hash(), curved.settings_hash());
+
+        //stopping and pausing reach the engine without clearing anything
+        let stopping = IFS { use_stopping_sl: true, stopping_sl: 10.0, ..ifs.clone() };
+        let paused = IFS { pause_rendering: true, ..ifs.clone() };
+        assert_eq!(ifs.get_hash(), stopping.get_hash());
+        assert_eq!(ifs.get_hash(), paused.get_hash());
+        assert_ne!(ifs.display_hash(ifs.settings_hash()), stopping.display_hash(stopping.settings_hash()));
+        assert_ne!(ifs.display_hash(ifs.settings_hash()), paused.display_hash(paused.settings_hash()));
+        //but pausing isn't saved, so it doesn't count as an edit
+        assert_eq!(ifs.settings_hash(), paused.settings_hash());
+    }
+
+    #[test]
+    fn test_histogram_digest() {
+        let mut ifs = IFS::default();
+        let digest = ifs.histogram_digest().unwrap();
+        let brighter = IFS { brightness: 3.0, title: String::from("Brighter"), ..ifs.clone() };
+        assert_eq!(brighter.histogram_digest().unwrap(), digest);
+        let wider = IFS { width: ifs.width + 1, ..ifs.clone() };
+        assert_ne!(wider.histogram_digest().unwrap(), digest);
+        let mut renamed = ifs.clone();
+        renamed.iterators[0].name = String::from("Renamed");
+        renamed.palette.name = String::from("HSV curves");
+        assert_eq!(renamed.histogram_digest().unwrap(), digest);
+        let mut rotated = ifs.clone();
+        rotated.palette.rotation += 1;
+        assert_ne!(rotated.histogram_digest().unwrap(), digest);
+
+        //parameter maps iterate in a different order every time, the digest can't
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        for (i, name) in names.iter().enumerate() {
+            ifs.iterators[0].real_params.insert(name.to_string(), i as f32);
+        }
+        let digest = ifs.histogram_digest().unwrap();
+        let mut reordered = ifs.clone();
+        reordered.iterators[0].real_params = names.iter().enumerate().rev().map(|(i, name)| (name.to_string(), i as f32)).collect();
+        assert_eq!(reordered.histogram_digest().unwrap(), digest);
+    }
+
+    #[test]
+    fn test_palette_resample() {
+        let palette = Palette {
+            name: String::from("bw"),
+            rotation: 0,
+            colors: vec![[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]],
+        };
+        let colors = palette.resample(5);
+        assert_eq!(colors.len(), 5);
+        assert_eq!(colors[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(colors[2], [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(colors[4], [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(palette.sample(0.25), colors[1]);
+
+        //rotation wraps the end around to the start
+        let rotated = Palette { rotation: 1, ..palette.clone() };
+        assert_eq!(rotated.resample(5)[0], [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(rotated.resample(5)[1], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(Palette { rotation: -4, ..palette.clone() }.resample(5), rotated.resample(5));
+
+        assert_eq!(Palette { colors: vec![], ..palette.clone() }.resample(3), vec![[1.0; 4]; 3]);
+
+        //a different palette is a different render
+        let mut ifs = IFS::default();
+        let hash = ifs.get_hash();
+        ifs.palette = palette;
+        assert_ne!(hash, ifs.get_hash());
+    }
+
+    #[test]
+    fn test_parse_ugr() {
+        let src = r#"
+fire {
+gradient:
+  title="Fire and Ice" smooth=no
+  index=0 color=255
+  index=200 color=16711680
+}
+plain {
+gradient:
+  index=0 color=65280
+}
+"#;
+        let palettes = parse_ugr(src).unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].name, "Fire and Ice");
+        assert_eq!(palettes[1].name, "plain");
+        assert_eq!(palettes[0].colors.len(), MAX_PALETTE_COLORS);
+
+        //COLORREFs are 0x00BBGGRR, so 255 is red
+        let fire = &palettes[0].colors;
+        assert_eq!(fire[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(fire[MAX_PALETTE_COLORS / 2], [0.0, 0.0, 1.0, 1.0]);
+        //between the stops it blends, and past the last one it wraps back toward the first
+        assert!(fire[MAX_PALETTE_COLORS / 4][0] > 0.0 && fire[MAX_PALETTE_COLORS / 4][2] > 0.0);
+        assert!(fire[MAX_PALETTE_COLORS - 1][0] > 0.9);
+        assert!(palettes[1].colors.iter().all(|c| *c == [0.0, 1.0, 0.0, 1.0]));
+
+        assert!(parse_ugr("nothing here").is_err());
+        assert!(parse_ugr("broken { index=0 color=255").is_err());
+        assert!(parse_ugr("empty { gradient: }").is_err());
+    }
+
+    #[test]
+    fn test_parse_map_and_gpl() {
+        let map = parse_map("0 0 0 black\n255 255 255 white, anything goes here\n", "bw").unwrap();
+        assert_eq!(map.name, "bw");
+        assert_eq!(map.colors.len(), MAX_PALETTE_COLORS);
+        assert_eq!(map.colors[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(map.colors[MAX_PALETTE_COLORS - 1], [1.0, 1.0, 1.0, 1.0]);
+        assert!(parse_map("not a palette", "x").is_err());
+
+        let gpl = "GIMP Palette\nName: Primaries\nColumns: 3\n# a comment\n255   0   0 Red\n  0   0 255\tBlue\n";
+        let gpl = parse_gpl(gpl, "file name").unwrap();
+        assert_eq!(gpl.name, "Primaries");
+        assert_eq!(gpl.colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(gpl.colors[MAX_PALETTE_COLORS - 1], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(parse_gpl("GIMP Palette\n0 0 0\n", "file name").unwrap().name, "file name");
+        assert!(parse_gpl("0 0 0\n", "x").is_err());
+        assert!(parse_gpl("GIMP Palette\n0 0 zero\n", "x").is_err());
+    }
+
+    #[test]
+    fn test_hsv_curves() {
+        use eframe::emath::pos2;
+
+        //points are in screen space, so y = 0 is the top of the curve, a value of 1
+        let ramp = vec![pos2(0.0, 1.0), pos2(0.5, 0.5), pos2(1.0, 0.0)];
+        assert_eq!(eval_curve(&ramp, 0.0), 0.0);
+        assert_eq!(eval_curve(&ramp, 0.25), 0.25);
+        assert_eq!(eval_curve(&ramp, 1.0), 1.0);
+
+        assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), [1.0, 0.0, 0.0]);
+        assert_eq!(hsv_to_rgb(2.0 / 3.0, 1.0, 1.0), [0.0, 0.0, 1.0]);
+        assert_eq!(hsv_to_rgb(1.0, 1.0, 1.0), [1.0, 0.0, 0.0]);
+        assert_eq!(hsv_to_rgb(0.3, 0.0, 0.5), [0.5, 0.5, 0.5]);
+
+        //full saturation red, fading in from black
+        let top = vec![pos2(0.0, 0.0), pos2(1.0, 0.0)];
+        let bottom = vec![pos2(0.0, 1.0), pos2(1.0, 1.0)];
+        let baked = bake_hsv_curves(&bottom, &top, &ramp);
+        assert_eq!(baked.len(), 256 * 3);
+        assert_eq!(baked[..3], [0, 0, 0]);
+        assert_eq!(baked[255 * 3..], [255, 0, 0]);
+        assert_eq!(baked[128 * 3..128 * 3 + 3], [128, 0, 0]);
+    }
+
+    #[test]
+    fn test_palette_generation() {
+        use strum::IntoEnumIterator;
+        for scheme in Scheme::iter() {
+            let palette = generate(scheme, 42);
+            assert_eq!(palette.colors.len(), MAX_PALETTE_COLORS);
+            assert_eq!(palette, generate(scheme, 42));
+            assert_ne!(palette.colors, generate(scheme, 43).colors);
+            assert!(palette.colors.iter().flatten().all(|c| (0.0..=1.0).contains(c)));
+        }
+
+        let step = Palette {
+            name: String::from("step"),
+            rotation: 3,
+            colors: vec![[0.0, 0.0, 0.0, 1.0], [0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0], [1.0, 1.0, 1.0, 1.0]],
+        };
+        let smoothed = smooth(&step, 1);
+        assert_eq!(smoothed.rotation, 3);
+        assert_eq!(smoothed.colors[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(smoothed.colors[1][0], 1.0 / 3.0);
+        assert_eq!(smoothed.colors[3], [1.0, 1.0, 1.0, 1.0]);
+
+        //green looks far lighter than blue at the same intensity
+        let primaries = Palette {
+            colors: vec![[0.0, 1.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]],
+            ..step.clone()
+        };
+        let sorted = sort_by_lightness(&primaries);
+        assert_eq!(sorted.colors[0], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(sorted.colors[3], [1.0, 1.0, 1.0, 1.0]);
+        assert!((lightness(&[1.0, 1.0, 1.0]) - 100.0).abs() < 1e-3);
+        assert_eq!(lightness(&[0.0, 0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_palette_from_image() {
+        let reds = [[0.9, 0.1, 0.1], [0.8, 0.0, 0.1], [1.0, 0.1, 0.0]];
+        let blues = [[0.0, 0.1, 0.9], [0.1, 0.0, 0.8], [0.0, 0.0, 1.0]];
+        let samples: Vec<[f32; 3]> = reds.iter().chain(&blues).cycle().take(60).cloned().collect();
+        let mut centers = kmeans(&samples, 2, 7);
+        centers.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        assert_eq!(centers.len(), 2);
+        assert!((centers[0][2] - 0.9).abs() < 1e-4 && (centers[1][0] - 0.9).abs() < 1e-4);
+        assert_eq!(kmeans(&samples, 2, 7), kmeans(&samples, 2, 7));
+        //asking for more clusters than there are colors gives what there is
+        assert_eq!(kmeans(&[[0.5; 3]; 10], 4, 0).len(), 1);
+
+        //half black, half white, with a transparent stripe of red that should be left out
+        let path = std::env::temp_dir().join(format!("ifsrs_palette_{}.png", std::process::id()));
+        let image = image::RgbaImage::from_fn(16, 16, |x, y| match (x, y) {
+            (_, 0) => image::Rgba([255, 0, 0, 0]),
+            (x, _) if x < 8 => image::Rgba([0, 0, 0, 255]),
+            _ => image::Rgba([255, 255, 255, 255]),
+        });
+        image.save(&path).unwrap();
+        let palette = from_image(&path, 2, 1);
+        std::fs::remove_file(&path).unwrap();
+        let palette = palette.unwrap();
+        assert_eq!(palette.colors.len(), MAX_PALETTE_COLORS);
+        assert_eq!(palette.colors[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(palette.colors[MAX_PALETTE_COLORS - 1], [1.0, 1.0, 1.0, 1.0]);
+        assert!(palette.colors.iter().all(|c| c[0] == c[1]));
+        assert!(from_image(std::path::Path::new("no such image.png"), 2, 1).is_err());
+    }
+
+    #[test]
+    fn test_response_curves() {
+        let identity = ResponseCurves::default();
+        let table = identity.bake(5);
+        assert_eq!(table.len(), 5);
+        assert_eq!(table[0], [0.0; 4]);
+        assert_eq!(table[2], [0.5; 4]);
+        assert_eq!(table[4], [1.0; 4]);
+
+        //overall goes first, then each channel's own curve, alpha only gets its own
+        let curves = ResponseCurves {
+            overall: vec![[0.0, 1.0], [1.0, 0.0]],
+            red: vec![[0.0, 0.0], [0.5, 1.0]],
+            alpha: vec![[0.0, 0.5], [1.0, 0.5]],
+            ..ResponseCurves::default()
+        };
+        let table = curves.bake(3);
+        assert_eq!(table[0], [1.0, 1.0, 1.0, 0.5]);
+        assert_eq!(table[1], [1.0, 0.5, 0.5, 0.5]);
+        assert_eq!(table[2], [0.0, 0.0, 0.0, 0.5]);
+        assert_eq!(ResponseCurves::eval(&[], 0.3), 0.3);
+        assert_eq!(ResponseCurves::eval(&[[0.2, 0.4], [0.8, 1.0]], 0.1), 0.4);
+
+        //they're saved with the world, and worlds from before them load flat
+        let ifs = IFS { response_curves: curves.clone(), ..IFS::default() };
+        let saved = ifs.to_world_toml().unwrap();
+        assert_eq!(IFS::from_world_toml(&saved).unwrap().response_curves, curves);
+        let mut table: toml::Table = toml::from_str(&saved).unwrap();
+        table["world"].as_table_mut().unwrap().remove("response_curves");
+        let old = IFS::from_world_toml(&toml::to_string(&table).unwrap()).unwrap();
+        assert_eq!(old.response_curves, ResponseCurves::default());
+
+        //hand edited ones are put in order, or turned away if they leave the square
+        let shuffled = IFS { response_curves: ResponseCurves { red: vec![[1.0, 1.0], [0.0, 0.0], [0.5, 0.7]], ..curves.clone() }, ..IFS::default() };
+        let loaded = IFS::from_world_toml(&shuffled.to_world_toml().unwrap()).unwrap();
+        assert_eq!(loaded.response_curves.red, vec![[0.0, 0.0], [0.5, 0.7], [1.0, 1.0]]);
+        let outside = IFS { response_curves: ResponseCurves { alpha: vec![[0.0, 0.0], [1.0, 1.5]], ..curves }, ..IFS::default() };
+        assert!(IFS::from_world_toml(&outside.to_world_toml().unwrap()).unwrap_err().to_string().contains("alpha"));
+    }
+
+    #[test]
+    fn test_tonal_histogram() {
+        let tone_map = ToneMapStruct { background: [0.5, 0.25, 0.0, 1.0], density_scale: 1.0, ..ToneMapStruct::new() };
+        let flat = ResponseCurves::default().bake(5);
+
+        //nothing there is all background
+        assert_eq!(tone_map_pixel([0.0; 4], &tone_map, &flat), ([0.5, 0.25, 0.0], 0.0));
+        //a dense white pixel covers it
+        let (color, a) = tone_map_pixel([1000.0; 4], &tone_map, &flat);
+        assert_eq!(a, 1.0);
+        assert!(color.iter().all(|c| *c > 0.99));
+
+        //the alpha curve decides how much background shows through, the color curves come after
+        let see_through = ResponseCurves { alpha: vec![[0.0, 0.0], [1.0, 0.5]], ..ResponseCurves::default() }.bake(5);
+        let (color, a) = tone_map_pixel([0.0, 0.0, 0.0, 1000.0], &tone_map, &see_through);
+        assert_eq!(a, 0.5);
+        assert_eq!(color, [0.25, 0.125, 0.0]);
+        let inverted = ResponseCurves { overall: vec![[0.0, 1.0], [1.0, 0.0]], ..ResponseCurves::default() }.bake(5);
+        assert_eq!(apply_color_curves([0.0, 0.25, 1.0], &inverted), [1.0, 0.75, 0.0]);
+        assert_eq!(response_curve(&inverted, 0.125, 3), 0.125);
+        assert_eq!(response_curve(&[], 0.3, 0), 0.3);
+
+        let samples = [[0.0; 4], [0.0; 4], [1000.0; 4]];
+        let histogram = TonalHistogram::from_samples(&samples, &tone_map, &flat);
+        assert_eq!(histogram.red.len(), TONAL_BINS);
+        assert_eq!(histogram.red[128], 2);
+        assert_eq!(histogram.green[64], 2);
+        assert_eq!(histogram.blue[0], 2);
+        assert_eq!(histogram.red[TONAL_BINS - 1], 1);
+        assert_eq!(histogram.luminance.iter().sum::<u32>(), 3);
+        assert_eq!(histogram.luminance[TONAL_BINS - 1], 1);
+    }
+
+    #[test]
+    fn test_image_export() {
+        let tone_map = ToneMapStruct { background: [0.5, 0.25, 0.0, 1.0], density_scale: 1.0, ..ToneMapStruct::new() };
+        let flat = ResponseCurves::default().bake(5);
+        let histogram = [[0.0; 4], [1000.0; 4]];
+
+        //the background is either painted in or left transparent
+        let opaque = tone_map_image(&histogram, &tone_map, &flat, ImageFormat::Png8, false);
+        assert_eq!(opaque[0], [0.5, 0.25, 0.0, 1.0]);
+        let transparent = tone_map_image(&histogram, &tone_map, &flat, ImageFormat::Png16, true);
+        assert_eq!(transparent[0], [0.0; 4]);
+        assert_eq!(transparent[1][3], 1.0);
+        assert!(transparent[1].iter().all(|c| *c > 0.99));
+
+        assert_eq!(ImageFormat::from_path(std::path::Path::new("a.PNG")), Some(ImageFormat::Png8));
+        assert_eq!(ImageFormat::from_path(std::path::Path::new("a.exr")), Some(ImageFormat::Exr));
+        assert_eq!(ImageFormat::from_path(std::path::Path::new("a.jpg")), None);
+
+        let dir = std::env::temp_dir();
+        let png_path = dir.join(format!("ifsrs_test_image_export_{}.png", std::process::id()));
+        save_image(&png_path, ImageFormat::Png16, 2, 1, &transparent, true).unwrap();
+        let png = image::open(&png_path).unwrap();
+        assert_eq!(png.color(), image::ColorType::Rgba16);
+        let png = png.into_rgba16();
+        assert_eq!(png.dimensions(), (2, 1));
+        assert_eq!(png.get_pixel(0, 0).0, [0; 4]);
+        assert_eq!(png.get_pixel(1, 0).0[3], u16::MAX);
+        let _ = std::fs::remove_file(&png_path);
+
+        //linear and past white where the PNG clips
+        let linear = tone_map_image(&histogram, &tone_map, &flat, ImageFormat::Exr, false);
+        let exr_path = dir.join(format!("ifsrs_test_image_export_{}.exr", std::process::id()));
+        save_image(&exr_path, ImageFormat::Exr, 2, 1, &linear, false).unwrap();
+        let exr = image::open(&exr_path).unwrap().into_rgba32f();
+        let _ = std::fs::remove_file(&exr_path);
+        assert_eq!(exr.dimensions(), (2, 1));
+        let [r, g, b, _] = exr.get_pixel(0, 0).0;
+        assert!((r - 0.214).abs() < 0.001); //0.5 in linear
+        assert!((g - 0.051).abs() < 0.001);
+        assert_eq!(b, 0.0);
+        assert!(exr.get_pixel(1, 0).0[..3].iter().all(|c| *c > 1.0));
+
+        assert!(save_image(&exr_path, ImageFormat::Exr, 3, 1, &linear, false).is_err());
+    }
+
+    #[test]
+    fn test_accumulation() {
+        let mut acc = Accumulation { world_digest: 42, width: 2, height: 1, seed: 1, invocation_iters: 512, dispatch_count: 10, accepted_samples: 1000, ..Accumulation::default() };
+        let mut bytes: Vec<u8> = bytemuck::cast_slice(&[[1f32, 2.0, 3.0, 4.0], [0.0, 0.0, 0.0, 0.5]]).to_vec();
+        let points_state: Vec<u8> = (0..POINTS_STATE_SIZE).map(|i| i as u8).collect();
+        bytes.extend_from_slice(&points_state);
+        acc.fill(&bytes).unwrap();
+        assert_eq!(acc.histogram[1], [0.0, 0.0, 0.0, 0.5]);
+        assert_eq!(acc.points_state, points_state);
+        assert!(acc.clone().fill(&bytes[..20]).is_err());
+
+        let path = std::env::temp_dir().join(format!("ifsrs_test_accumulation_{}.ifsacc", std::process::id()));
+        acc.save(&path).unwrap();
+        assert_eq!(Accumulation::load(&path).unwrap(), acc);
+
+        //a bad header is turned away before anything much is allocated for it
+        let saved = std::fs::read(&path).unwrap();
+        let bad_path = std::env::temp_dir().join(format!("ifsrs_test_accumulation_bad_{}.ifsacc", std::process::id()));
+        for (offset, value) in [(20, u32::MAX as u64), (24, u32::MAX as u64), (52, u64::MAX), (52, 3)] {
+            let mut bad = saved.clone();
+            let size = if offset == 52 { 8 } else { 4 };
+            bad[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+            std::fs::write(&bad_path, bad).unwrap();
+            assert!(Accumulation::load(&bad_path).is_err());
+        }
+        //sides that pass on their own can still be too many pixels, and a size that's allowed
+        //but isn't in the file only gets as far as the data does
+        for (side, error) in [(1u32 << 15, "larger than any render"), (1 << 12, "cut short")] {
+            let mut bad = saved.clone();
+            bad[20..24].copy_from_slice(&side.to_le_bytes());
+            bad[24..28].copy_from_slice(&side.to_le_bytes());
+            std::fs::write(&bad_path, bad).unwrap();
+            let message = format!("{:#}", Accumulation::load(&bad_path).unwrap_err());
+            assert!(message.contains(error), "{message}");
+        }
+        let _ = std::fs::remove_file(&bad_path);
+
+        //other seeds add up, the same seed is the same samples over again
+        let other = Accumulation { seed: 2, dispatch_count: 5, ..acc.clone() };
+        let other_path = std::env::temp_dir().join(format!("ifsrs_test_accumulation_2_{}.ifsacc", std::process::id()));
+        other.save(&other_path).unwrap();
+        let merged = Accumulation::load_merged(&[&path, &other_path]).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&other_path);
+        assert_eq!(merged.histogram[0], [2.0, 4.0, 6.0, 8.0]);
+        assert_eq!((merged.dispatch_count, merged.accepted_samples, merged.seed), (15, 2000, 1));
+        assert!(acc.clone().merge(&acc).is_err());
+        assert!(acc.clone().merge(&Accumulation { world_digest: 7, ..other.clone() }).is_err());
+        assert!(acc.clone().merge(&Accumulation { invocation_iters: 64, ..other }).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Translates the subset of GLSL that IFSRenderer transform plugins are written in to WGSL,
+//! so they can be spliced into ifs_kernel.wgsl.
+//!
+//! This is a token-level rewrite, not a compiler. It handles:
+//! * declarations (`vec3 a = ...;` -> `var a: vec3<f32> = ...;`, `const float` -> `let`)
+//! * constructors (`float(x)` -> `f32(x)`, `mat3(1.0)` -> a diagonal `mat3x3<f32>`)
+//! * `mod(x, y)` (GLSL's floored modulo, which WGSL's `%` is not), two-argument `atan`
+//! * unbraced `if`/`else`/`for`/`while` bodies, which WGSL requires braces around
+//! * params referred to with the wrong case (`size` for `Size`)
+//! * `random(next)` -> `random()`
+//!
+//! WGSL passes through untouched, and line numbers are preserved so that errors reported
+//! against the translated source still point at the right line of the original.
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(String),
+    Punct(char),
+    Trivia(String), //whitespace and comments
+}
+
+fn tokenize(src: &str) -> Vec<Tok> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            toks.push(Tok::Trivia(chars[start..i].iter().collect()));
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            toks.push(Tok::Trivia(chars[start..i].iter().collect()));
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i = (i + 2).min(chars.len());
+            toks.push(Tok::Trivia(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            toks.push(Tok::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) {
+            while i < chars.len() {
+                let n = chars[i];
+                let exponent_sign = (n == '-' || n == '+') && matches!(chars[i - 1], 'e' | 'E')
+                    && !chars[start..i].contains(&'x');
+                if n.is_alphanumeric() || n == '.' || exponent_sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            toks.push(Tok::Num(chars[start..i].iter().collect()));
+        } else {
+            toks.push(Tok::Punct(c));
+            i += 1;
+        }
+    }
+    toks
+}
+
+fn untokenize(toks: &[Tok]) -> String {
+    toks.iter().map(|t| match t {
+        Tok::Ident(s) | Tok::Num(s) | Tok::Trivia(s) => s.as_str().to_owned(),
+        Tok::Punct(c) => c.to_string(),
+    }).collect()
+}
+
+/// Index of the first token at or after `i` that isn't whitespace or a comment
+fn next_sig(toks: &[Tok], i: usize) -> Option<usize> {
+    (i..toks.len()).find(|&j| !matches!(toks[j], Tok::Trivia(_)))
+}
+
+fn prev_sig(toks: &[Tok], i: usize) -> Option<usize> {
+    (0..i).rev().find(|&j| !matches!(toks[j], Tok::Trivia(_)))
+}
+
+/// Given the index of an opening paren, the index of its partner
+fn matching_paren(toks: &[Tok], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (j, t) in toks.iter().enumerate().skip(open) {
+        match t {
+            Tok::Punct('(') => depth += 1,
+            Tok::Punct(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(j);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the tokens between a pair of parens on top-level commas
+fn split_args(toks: &[Tok]) -> Vec<Vec<Tok>> {
+    let mut args = vec![vec![]];
+    let mut depth = 0;
+    for t in toks {
+        match t {
+            Tok::Punct('(') | Tok::Punct('[') => depth += 1,
+            Tok::Punct(')') | Tok::Punct(']') => depth -= 1,
+            Tok::Punct(',') if depth == 0 => {
+                args.push(vec![]);
+                continue;
+            }
+            _ => {}
+        }
+        args.last_mut().unwrap().push(t.clone());
+    }
+    if args.len() == 1 && args[0].iter().all(|t| matches!(t, Tok::Trivia(_))) {
+        args.clear();
+    }
+    args
+}
+
+/// GLSL type name -> WGSL type name
+fn wgsl_type(glsl: &str) -> Option<String> {
+    let t = match glsl {
+        "float" => "f32".to_owned(),
+        "int" => "i32".to_owned(),
+        "uint" => "u32".to_owned(),
+        "bool" => "bool".to_owned(),
+        "vec2" | "vec3" | "vec4" => format!("{glsl}<f32>"),
+        "ivec2" | "ivec3" | "ivec4" => format!("vec{}<i32>", &glsl[4..]),
+        "uvec2" | "uvec3" | "uvec4" => format!("vec{}<u32>", &glsl[4..]),
+        "bvec2" | "bvec3" | "bvec4" => format!("vec{}<bool>", &glsl[4..]),
+        "mat2" | "mat3" | "mat4" => format!("mat{0}x{0}<f32>", &glsl[3..]),
+        "mat2x2" | "mat2x3" | "mat2x4" | "mat3x2" | "mat3x3" | "mat3x4" | "mat4x2" | "mat4x3" | "mat4x4" =>
+            format!("{glsl}<f32>"),
+        _ => return None,
+    };
+    Some(t)
+}
+
+/// Square matrix dimension, for expanding `matN(s)` into a diagonal
+fn square_mat_dim(glsl: &str) -> Option<usize> {
+    match glsl {
+        "mat2" | "mat2x2" => Some(2),
+        "mat3" | "mat3x3" => Some(3),
+        "mat4" | "mat4x4" => Some(4),
+        _ => None,
+    }
+}
+
+/// Expansions repeat their arguments, so fold any newlines inside them to spaces
+/// and put the same number back at the end to keep line numbers stable
+fn keep_lines(original: &[Tok], expansion: String) -> Vec<Tok> {
+    let newlines = untokenize(original).matches('\n').count();
+    let mut s = expansion.replace('\n', " ");
+    s.push_str(&"\n".repeat(newlines));
+    tokenize(&s)
+}
+
+/// Rewrites calls and constructors whose WGSL spelling differs
+fn rewrite_calls(toks: &[Tok]) -> Vec<Tok> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < toks.len() {
+        if let Tok::Ident(name) = &toks[i] {
+            let open = next_sig(toks, i + 1).filter(|&o| toks[o] == Tok::Punct('('));
+            let close = open.and_then(|o| matching_paren(toks, o));
+            let member = prev_sig(toks, i).is_some_and(|p| toks[p] == Tok::Punct('.'));
+            if let (Some(open), Some(close), false) = (open, close, member) {
+                let args: Vec<String> = split_args(&toks[open + 1..close]).iter()
+                    .map(|a| untokenize(&rewrite_calls(a)).trim().to_owned())
+                    .collect();
+                let span = &toks[i..=close];
+                let expansion = match (name.as_str(), args.len()) {
+                    ("mod", 2) => Some(format!("(({0}) - ({1}) * floor(({0}) / ({1})))", args[0], args[1])),
+                    ("atan", 2) => Some(format!("atan2({}, {})", args[0], args[1])),
+                    ("random", 1) if args[0] == "next" => Some(String::from("random()")),
+                    (t, 1) if square_mat_dim(t).is_some() => {
+                        let n = square_mat_dim(t).unwrap();
+                        let cells: Vec<String> = (0..n * n)
+                            .map(|k| if k % (n + 1) == 0 { format!("({})", args[0]) } else { String::from("0.0") })
+                            .collect();
+                        Some(format!("{}({})", wgsl_type(t).unwrap(), cells.join(", ")))
+                    }
+                    (t, _) if wgsl_type(t).is_some() =>
+                        Some(format!("{}({})", wgsl_type(t).unwrap(), args.join(", "))),
+                    _ => None,
+                };
+                if let Some(e) = expansion {
+                    out.extend(keep_lines(span, e));
+                    i = close + 1;
+                    continue;
+                }
+                //not ours, but its arguments might be
+                out.extend_from_slice(&toks[i..=open]);
+                out.extend(rewrite_calls(&toks[open + 1..close]));
+                out.push(Tok::Punct(')'));
+                i = close + 1;
+                continue;
+            }
+        }
+        out.push(toks[i].clone());
+        i += 1;
+    }
+    out
+}
+
+/// `T name` -> `var name: T'`, `const T name` -> `let name: T'`
+fn rewrite_declarations(toks: &[Tok]) -> Vec<Tok> {
+    let mut out = toks.to_vec();
+    for i in 0..out.len() {
+        let Tok::Ident(t) = &out[i] else { continue };
+        let Some(wgsl) = wgsl_type(t) else { continue };
+        let Some(name_idx) = next_sig(&out, i + 1) else { continue };
+        if !matches!(&out[name_idx], Tok::Ident(n) if wgsl_type(n).is_none()) {
+            continue;
+        }
+        let keyword = match prev_sig(&out, i) {
+            Some(p) if out[p] == Tok::Ident(String::from("const")) => {
+                out[p] = Tok::Ident(String::from("let"));
+                None
+            }
+            _ => Some(Tok::Ident(String::from("var"))),
+        };
+        match keyword {
+            Some(k) => out[i] = k,
+            //`let` already stands in for the type's slot, so drop the type and the space after it
+            None => {
+                out[i] = Tok::Trivia(String::new());
+                if let Some(Tok::Trivia(s)) = out.get(i + 1) {
+                    if !s.contains('\n') {
+                        out[i + 1] = Tok::Trivia(String::new());
+                    }
+                }
+            }
+        }
+        if let Tok::Ident(name) = &out[name_idx] {
+            out[name_idx] = Tok::Ident(format!("{name}: {wgsl}"));
+        }
+    }
+    out
+}
+
+/// Index of the `;` ending the statement that starts at `i`
+fn statement_end(toks: &[Tok], i: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (j, t) in toks.iter().enumerate().skip(i) {
+        match t {
+            Tok::Punct('(') | Tok::Punct('{') | Tok::Punct('[') => depth += 1,
+            Tok::Punct(')') | Tok::Punct('}') | Tok::Punct(']') => depth -= 1,
+            Tok::Punct(';') if depth == 0 => return Some(j),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// WGSL has no unbraced bodies
+fn brace_bodies(toks: &[Tok]) -> Vec<Tok> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < toks.len() {
+        let header = match &toks[i] {
+            Tok::Ident(kw) if kw == "if" || kw == "for" || kw == "while" => next_sig(toks, i + 1)
+                .filter(|&o| toks[o] == Tok::Punct('('))
+                .and_then(|o| matching_paren(toks, o)),
+            Tok::Ident(kw) if kw == "else" => Some(i),
+            _ => None,
+        };
+        let Some(header_end) = header else {
+            out.push(toks[i].clone());
+            i += 1;
+            continue;
+        };
+        out.extend_from_slice(&toks[i..=header_end]);
+        i = header_end + 1;
+
+        let Some(body) = next_sig(toks, i) else { continue };
+        let braced_already = toks[body] == Tok::Punct('{') || toks[body] == Tok::Ident(String::from("if"));
+        let Some(end) = statement_end(toks, body).filter(|_| !braced_already) else { continue };
+        out.extend_from_slice(&toks[i..body]);
+        out.push(Tok::Punct('{'));
+        out.push(Tok::Trivia(String::from(" ")));
+        out.extend(brace_bodies(&toks[body..=end]));
+        out.push(Tok::Trivia(String::from(" ")));
+        out.push(Tok::Punct('}'));
+        i = end + 1;
+    }
+    out
+}
+
+/// Plugins were allowed to be sloppy about the case of param names, WGSL isn't
+fn fix_param_case(toks: &mut [Tok], params: &[&str]) {
+    let locals: Vec<String> = (0..toks.len())
+        .filter(|&i| matches!(&toks[i], Tok::Ident(k) if k == "var" || k == "let"))
+        .filter_map(|i| next_sig(toks, i + 1))
+        .filter_map(|j| match &toks[j] {
+            Tok::Ident(n) => Some(n.split(':').next().unwrap().to_owned()),
+            _ => None,
+        })
+        .collect();
+    for i in 0..toks.len() {
+        let member = prev_sig(toks, i).is_some_and(|p| toks[p] == Tok::Punct('.'));
+        let Tok::Ident(name) = &toks[i] else { continue };
+        if member || params.contains(&name.as_str()) || locals.contains(name) {
+            continue;
+        }
+        let mut candidates = params.iter().filter(|p| p.eq_ignore_ascii_case(name));
+        if let (Some(p), None) = (candidates.next(), candidates.next()) {
+            toks[i] = Tok::Ident(p.to_string());
+        }
+    }
+}
+
+/// Translates a transform snippet from GLSL to WGSL.
+/// `params` are the names of the transform's real and vec3 params.
+pub fn glsl_to_wgsl(src: &str, params: &[&str]) -> String {
+    let toks = tokenize(src);
+    let toks = rewrite_calls(&toks);
+    let toks = rewrite_declarations(&toks);
+    let mut toks = brace_bodies(&toks);
+    fix_param_case(&mut toks, params);
+    untokenize(&toks)
+}
@@ -1 +1,3 @@
-pub mod math_extensions;
\ No newline at end of file
+pub mod math_extensions;
+pub mod glsl;
+pub mod lru_cache;
\ No newline at end of file
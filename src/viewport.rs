@@ -38,7 +38,8 @@ impl Default for Viewport {
 
 
 impl Viewport {
-    pub fn ui_content(&mut self, ui: &mut Ui, tex: TextureId) -> egui::Response {
+    /// Shows the render, `size` pixels of it, as large as fits without stretching
+    pub fn ui_content(&mut self, ui: &mut Ui, tex: TextureId, size: [u32; 2]) -> egui::Response {
         let speed_scale = 0.01;
         self.drag_delta = vec2(0.0,0.0);
         self.pos_delta = Vector3::new(0.0, 0.0, 0.0);
@@ -46,16 +47,17 @@ impl Viewport {
 
         let (response, painter) =
             ui.allocate_painter(Vec2::new(ui.available_width(), ui.available_height()), Sense::drag());
-        let rect = response.rect;
+        let image_size = vec2(size[0].max(1) as f32, size[1].max(1) as f32);
+        let rect = Rect::from_center_size(response.rect.center(), image_size * (response.rect.size() / image_size).min_elem());
         painter.image(tex, rect, Rect::from_min_max(pos2(0.0,0.0), pos2(1.0,1.0)), Color32::WHITE);
         let to_screen = emath::RectTransform::from_to(
             Rect::from_min_size(Pos2::ZERO, vec2(1.0,1.0)),
             rect,
         );
 
-        self.width = rect.max[0] - rect.min[0];
-        self.height = rect.max[1] - rect.min[1];
-        let scale : Vec2 = vec2(1.0/ self.width, 1.0/ self.height);
+        self.width = response.rect.width();
+        self.height = response.rect.height();
+        let scale : Vec2 = vec2(1.0/ rect.width(), 1.0/ rect.height());
 
         self.drag_delta += response.drag_delta() * scale;
 